@@ -39,7 +39,8 @@ fn navigation(node_ids: &[NodeId], instance: &RunningCouncil) -> Markup {
 }
 
 async fn render_node(node_ids: &[NodeId], instance: &RunningCouncil) -> Markup {
-    let cluster = instance.council_instance.cluster().await.unwrap();
+    let cluster = instance.council_instance.current_cluster();
+    let cluster = cluster.as_ref();
     let mut members = cluster
         .cluster_view
         .known_members
@@ -55,6 +56,11 @@ async fn render_node(node_ids: &[NodeId], instance: &RunningCouncil) -> Markup {
             strong { "ID : " } (cluster.this_node_id.to_string())
             br;
             strong { "Has converged? : " } (cluster.has_converged().to_string())
+            ul {
+                @for blocker in cluster.convergence_blockers() {
+                    li { (format!("{:?}", blocker)) }
+                }
+            }
             h2 { "Know members"}
             div .card-grid {
                 @for member_view in members {
@@ -69,6 +75,13 @@ async fn render_node(node_ids: &[NodeId], instance: &RunningCouncil) -> Markup {
                         }
                         ul {
                             li { strong { "Advertised URL: " } (member_view.advertised_addr.to_string()) }
+                            li { strong { "Protocol version: " } (member_view.protocol.version) }
+                            @if !member_view.roles.is_empty() {
+                                li { strong { "Roles: " } (member_view.roles.iter().cloned().collect::<Vec<_>>().join(", ")) }
+                            }
+                            @for (key, entry) in &member_view.application_state {
+                                li { strong { (key) ": " } (entry.value) }
+                            }
                             @if let Some(state) = &member_view.state {
                                 li { strong { "Version: " } (state.version) }
                                 li { strong { "Status: " } (state.node_status.to_string()) }
@@ -106,30 +119,45 @@ async fn render_node(node_ids: &[NodeId], instance: &RunningCouncil) -> Markup {
                     tr {
                         th { "Node" }
                         th { "Heartbeat" }
-                        th { "Mean heartbeat time" }
-                        th { "Std. dev." }
-                        th { "Min" }
-                        th { "Max" }
-                        th { "Phi" }
+                        th { "Live" }
+                        th { "Suspicion level" }
                     }
                 }
                 tbody {
-                    @for (id, m) in cluster.failure_detector.members() {
+                    @for (id, last_heartbeat) in cluster.failure_detector.monitored_members() {
                         tr {
                             td { (id.unique_id.to_string()) }
-                            td { (m.last_heartbeat) }
-                            td { (m.heartbeats_intervals_mean.map_or(UNKNOWN.to_string(), |d| format!("{}ms", d.as_millis()))) }
-                            td { (m.hearbeats_interval_std_dev.map_or(UNKNOWN.to_string(), |d| format!("{}ms", d.as_millis()))) }
-                            td { (m.heartbeats_min_interval.map_or(UNKNOWN.to_string(), |d| format!("{}ms", d.as_millis()))) }
-                            td { (m.heartbeats_max_interval.map_or(UNKNOWN.to_string(), |d| format!("{}ms", d.as_millis()))) }
-                            td { (m.phi(now).map_or(UNKNOWN.to_string(), |p| p.to_string())) }
+                            td { (last_heartbeat) }
+                            td { (if cluster.failure_detector.is_live(id, now) { "Yes" } else { "No" }) }
+                            td { (cluster.failure_detector.suspicion_level(id, now).map_or(UNKNOWN.to_string(), |p| p.to_string())) }
+                        }
+                    }
+                }
+            }
+            h2 { "Reachability" }
+            table {
+                thead {
+                    tr {
+                        th { "Observer" }
+                        th { "Subject" }
+                        th { "Status" }
+                        th { "Version" }
+                    }
+                }
+                tbody {
+                    @for record in cluster.reachability().records() {
+                        tr {
+                            td { (record.observer.unique_id.to_string()) }
+                            td { (record.subject.unique_id.to_string()) }
+                            td { (format!("{:?}", record.status)) }
+                            td { (record.version) }
                         }
                     }
                 }
             }
             h2 { "Raw cluster state (JSON)" }
             div .card id="raw-cluster-state" {
-                pre { (serde_json::ser::to_string_pretty(&cluster).unwrap()) }
+                pre { (serde_json::ser::to_string_pretty(cluster).unwrap()) }
             }
         }
     }
@@ -1,26 +1,57 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeSet, HashMap, HashSet},
     sync::Arc,
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{mpsc, watch};
 use url::Url;
 
+#[cfg(feature = "tls")]
+use crate::grpc::TlsConfig;
 use crate::{
-    cluster::{failure_detector::FailureDetector, views::ClusterView, Cluster},
-    grpc::{client::CouncilClient, DefaultTonicChannelFactory, TonicChannelFactory},
+    cluster::{
+        admission::AdmissionHook,
+        bootstrap::{Bootstrap, BootstrapSettings},
+        downing::DowningStrategy,
+        failure_detector::{FailureDetector, PhiAccrualFailureDetector, PhiAccrualSettings},
+        swim::{Swim, SwimSettings},
+        views::ClusterView,
+        Cluster,
+    },
+    events::{EventPublisher, MembershipSnapshot},
+    grpc::{client::CouncilClient, DefaultTonicChannelFactory, Keyring, TonicChannelFactory},
     node::NodeId,
+    protocol::DEFAULT_CLUSTER_NAME,
     Council,
 };
 
+#[cfg(feature = "tls")]
+const TLS_WITH_CHANNEL_FACTORY: &str =
+    "TLS can't be configured along with a custom TonicChannelFactory";
+
 pub struct CouncilBuilder {
     this_node_advertised_url: Url,
     this_node_id: NodeId,
+    cluster_name: String,
+    keyring: Option<Keyring>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>,
     peer_nodes: HashSet<Url>,
-    failure_detector_phi_threshold: f64,
+    roles: BTreeSet<String>,
+    failure_detector: Option<Box<dyn FailureDetector>>,
+    phi_accrual_settings: PhiAccrualSettings,
     gossip_interval: Duration,
-    tonic_channel_factory: Arc<dyn TonicChannelFactory + Send + Sync>,
+    heartbeat_interval: Duration,
+    monitored_by_nr_of_members: usize,
+    tombstone_ttl: Duration,
+    admission_hook: Option<Arc<dyn AdmissionHook>>,
+    join_token: Option<String>,
+    downing_strategy: Option<Arc<dyn DowningStrategy>>,
+    downing_stable_after: Duration,
+    bootstrap: Option<BootstrapSettings>,
+    swim: Option<SwimSettings>,
+    tonic_channel_factory: Option<Arc<dyn TonicChannelFactory + Send + Sync>>,
 }
 
 impl CouncilBuilder {
@@ -29,40 +60,204 @@ impl CouncilBuilder {
         Self {
             this_node_advertised_url,
             this_node_id,
+            cluster_name: DEFAULT_CLUSTER_NAME.to_string(),
+            keyring: None,
+            #[cfg(feature = "tls")]
+            tls: None,
             peer_nodes: HashSet::new(),
-            failure_detector_phi_threshold: 8.0,
+            roles: BTreeSet::new(),
+            failure_detector: None,
+            phi_accrual_settings: PhiAccrualSettings::default(),
             gossip_interval: Duration::from_millis(1500),
-            tonic_channel_factory: Arc::new(DefaultTonicChannelFactory::new()),
+            heartbeat_interval: Duration::from_secs(1),
+            monitored_by_nr_of_members: 9,
+            tombstone_ttl: Duration::from_secs(24 * 60 * 60),
+            admission_hook: None,
+            join_token: None,
+            downing_strategy: None,
+            downing_stable_after: Duration::from_secs(20),
+            bootstrap: None,
+            swim: None,
+            tonic_channel_factory: None,
         }
     }
+    /// Sets the [TonicChannelFactory] opening the channels to other nodes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [TLS](Self::with_tls) is configured, as it comes with its own factory.
     pub fn with_tonic_channel_factory<F: TonicChannelFactory + Send + Sync + 'static>(
-        mut self,
+        self,
         factory: F,
     ) -> Self {
-        self.tonic_channel_factory = Arc::new(factory);
-        self
+        self.with_tonic_channel_factory_arc(Arc::new(factory))
     }
+    /// Like [with_tonic_channel_factory](Self::with_tonic_channel_factory), with a shared factory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [TLS](Self::with_tls) is configured, as it comes with its own factory.
     pub fn with_tonic_channel_factory_arc<F: TonicChannelFactory + Send + Sync + 'static>(
         mut self,
         factory: Arc<F>,
     ) -> Self {
-        self.tonic_channel_factory = factory;
+        #[cfg(feature = "tls")]
+        assert!(self.tls.is_none(), "{}", TLS_WITH_CHANNEL_FACTORY);
+        self.tonic_channel_factory = Some(factory);
+        self
+    }
+    /// Sets the name of the cluster the running node belongs to. Defaults to "council".
+    /// The name is carried by every gossip message, and nodes turn away messages from nodes of other clusters,
+    /// so that separate clusters sharing a network never merge by mistake. Nodes that predate protocol versioning
+    /// don't send any cluster name, so only the nodes of the default cluster gossip with them.
+    pub fn with_cluster_name(mut self, cluster_name: impl Into<String>) -> Self {
+        self.cluster_name = cluster_name.into();
+        self
+    }
+    /// Authenticates every gossip message with the given [Keyring]: messages are signed with its primary key,
+    /// and messages that aren't signed by any of its keys are rejected.
+    /// Every node of the cluster must be configured with a keyring sharing at least one key,
+    /// and keep its clock within 30 seconds of the others, as older messages are rejected as replays.
+    pub fn with_keyring(mut self, keyring: Keyring) -> Self {
+        self.keyring = Some(keyring);
+        self
+    }
+    /// Secures the communications between nodes with [mutual TLS](crate::grpc::TlsConfig): the running node opens
+    /// TLS channels to other nodes, and [Council::server_tls_config] returns the configuration of the server
+    /// hosting the gossip service. Peer URLs must use the `https` scheme.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a [TonicChannelFactory] is set, as TLS comes with its own factory.
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        assert!(
+            self.tonic_channel_factory.is_none(),
+            "{}",
+            TLS_WITH_CHANNEL_FACTORY
+        );
+        self.tls = Some(tls);
         self
     }
     pub fn with_peer_nodes(mut self, peer_nodes: &[Url]) -> Self {
         self.peer_nodes.extend(peer_nodes.iter().cloned());
         self
     }
+    /// Declares the roles of the running node, e.g. "frontend" or "worker".
+    /// Roles are gossiped along with the membership of the node, and cannot change until the node is restarted.
+    pub fn with_roles<S: Into<String>>(mut self, roles: impl IntoIterator<Item = S>) -> Self {
+        self.roles.extend(roles.into_iter().map(Into::into));
+        self
+    }
+    /// Sets the phi value above which a member is considered unreachable. Defaults to 8.0.
+    /// Lower values detect failures faster, at the cost of more false positives.
     pub fn with_failure_detector_phi_threshold(mut self, threshold: f64) -> Self {
-        self.failure_detector_phi_threshold = threshold;
+        self.phi_accrual_settings.phi_threshold = threshold;
+        self
+    }
+    /// Sets how many intervals between heartbeats the failure detector keeps per member. Defaults to 100.
+    pub fn with_failure_detector_window_size(mut self, window_size: usize) -> Self {
+        self.phi_accrual_settings.window_size = window_size;
+        self
+    }
+    /// Sets the minimum standard deviation of the intervals between heartbeats. Defaults to 100ms.
+    pub fn with_failure_detector_min_std_deviation(mut self, min_std_deviation: Duration) -> Self {
+        self.phi_accrual_settings.min_std_deviation = min_std_deviation;
+        self
+    }
+    /// Sets how long heartbeats may pause on top of their usual interval before a member is suspected.
+    /// Defaults to 3 seconds.
+    pub fn with_failure_detector_acceptable_heartbeat_pause(mut self, pause: Duration) -> Self {
+        self.phi_accrual_settings.acceptable_heartbeat_pause = pause;
+        self
+    }
+    /// Sets the interval between heartbeats the failure detector assumes until it has measured actual intervals.
+    /// Defaults to 1 second.
+    pub fn with_failure_detector_first_heartbeat_estimate(mut self, estimate: Duration) -> Self {
+        self.phi_accrual_settings.first_heartbeat_estimate = estimate;
+        self
+    }
+    /// Sets all the [PhiAccrualSettings] at once
+    pub fn with_failure_detector_settings(mut self, settings: PhiAccrualSettings) -> Self {
+        self.phi_accrual_settings = settings;
+        self
+    }
+    /// Replaces the default [PhiAccrualFailureDetector] with another [FailureDetector].
+    /// The `with_failure_detector_*` settings only apply to the default detector.
+    pub fn with_failure_detector<D: FailureDetector + 'static>(
+        mut self,
+        failure_detector: D,
+    ) -> Self {
+        self.failure_detector = Some(Box::new(failure_detector));
         self
     }
     pub fn with_gossip_interval(mut self, interval_duration: Duration) -> Self {
         self.gossip_interval = interval_duration;
         self
     }
+    /// Sets how often the running node sends heartbeats to the members monitoring it. Defaults to 1 second.
+    /// Heartbeats are small messages sent independently of gossip, so this can be shorter than the gossip interval.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval_duration` is zero.
+    pub fn with_heartbeat_interval(mut self, interval_duration: Duration) -> Self {
+        assert!(
+            !interval_duration.is_zero(),
+            "The heartbeat interval must be greater than zero"
+        );
+        self.heartbeat_interval = interval_duration;
+        self
+    }
+    /// Sets how many members monitor each member through heartbeats. Defaults to 9.
+    pub fn with_monitored_by_nr_of_members(mut self, nr_of_members: usize) -> Self {
+        self.monitored_by_nr_of_members = nr_of_members;
+        self
+    }
+    /// Sets how long the tombstones of removed members are kept, and gossiped, before being forgotten.
+    /// Defaults to 24 hours.
+    pub fn with_tombstone_ttl(mut self, tombstone_ttl: Duration) -> Self {
+        self.tombstone_ttl = tombstone_ttl;
+        self
+    }
+    /// Sets the [AdmissionHook] that decides which nodes may join the cluster.
+    /// By default, any node that gossips with the running node is admitted.
+    pub fn with_admission_hook<H: AdmissionHook + 'static>(mut self, hook: H) -> Self {
+        self.admission_hook = Some(Arc::new(hook));
+        self
+    }
+    /// Sets the token the running node presents to the [AdmissionHook]s of other nodes.
+    /// The token is only sent along with the views the running node pushes to its peers, and never gossiped
+    /// further, but use a [Keyring] or TLS to keep it private in transit.
+    pub fn with_join_token(mut self, join_token: impl Into<String>) -> Self {
+        self.join_token = Some(join_token.into());
+        self
+    }
+    /// Sets the [DowningStrategy] the leader applies to unreachable members.
+    /// By default, no strategy is applied and unreachable members are never downed automatically.
+    pub fn with_downing_strategy<S: DowningStrategy + 'static>(mut self, strategy: S) -> Self {
+        self.downing_strategy = Some(Arc::new(strategy));
+        self
+    }
+    /// Sets how long the set of unreachable members must remain unchanged before the
+    /// [DowningStrategy] is applied. Defaults to 20 seconds.
+    pub fn with_downing_stable_after(mut self, stable_after: Duration) -> Self {
+        self.downing_stable_after = stable_after;
+        self
+    }
+    /// Makes the running node [bootstrap](crate::cluster::bootstrap) its cluster: it probes the given contact points
+    /// and either joins the cluster they are part of, or forms a new cluster along with them.
+    pub fn with_bootstrap(mut self, settings: BootstrapSettings) -> Self {
+        self.bootstrap = Some(settings);
+        self
+    }
+    /// Enables [SWIM-style probing](crate::cluster::swim): on every gossip round, the running node pings one member,
+    /// directly then through other members, and suspects it if it doesn't answer.
+    pub fn with_swim_probing(mut self, settings: SwimSettings) -> Self {
+        self.swim = Some(settings);
+        self
+    }
     pub fn build(self) -> Council {
-        let (cluster_events_sender, _) = broadcast::channel(10);
         let (message_sender, message_receiver) = mpsc::channel(20);
 
         let outgoing_gossip_interval = tokio::time::interval(self.gossip_interval);
@@ -73,9 +268,14 @@ impl CouncilBuilder {
             .filter(|u| u != &self.this_node_advertised_url)
             .collect();
 
-        let cluster_view =
-            ClusterView::initial(self.this_node_id, self.this_node_advertised_url.clone());
-        let failure_detector = FailureDetector::new(self.this_node_id);
+        let cluster_view = ClusterView::initial(
+            self.this_node_id,
+            self.this_node_advertised_url.clone(),
+            self.roles,
+        );
+        let failure_detector = self
+            .failure_detector
+            .unwrap_or_else(|| Box::new(PhiAccrualFailureDetector::new(self.phi_accrual_settings)));
 
         let cluster = Cluster {
             this_node_id: self.this_node_id,
@@ -84,6 +284,18 @@ impl CouncilBuilder {
             unknwon_peer_nodes: peer_nodes.clone(),
             peer_nodes,
             failure_detector,
+            downing_strategy: self.downing_strategy,
+            downing_stable_after: self.downing_stable_after,
+            unreachable_since: HashMap::new(),
+            suspected_since: HashMap::new(),
+            unreachable_members_changed_at: Instant::now(),
+            bootstrap: self.bootstrap.map(Bootstrap::new),
+            swim: self.swim.map(Swim::new),
+            heartbeat_interval: self.heartbeat_interval,
+            monitored_by_nr_of_members: self.monitored_by_nr_of_members,
+            tombstone_ttl: self.tombstone_ttl,
+            admission_hook: self.admission_hook,
+            admission_denials: HashMap::new(),
         };
         log::info!(
             "Creating Council instance with id {} and {} peer nodes",
@@ -91,8 +303,22 @@ impl CouncilBuilder {
             cluster.unknwon_peer_nodes.len()
         );
 
+        let (cluster_sender, cluster_receiver) = watch::channel(Arc::new(cluster.clone()));
+        let event_publisher = EventPublisher::new(MembershipSnapshot::of(&cluster), 10);
+
+        let tonic_channel_factory = self.tonic_channel_factory.unwrap_or_else(|| {
+            #[cfg(feature = "tls")]
+            if let Some(tls) = &self.tls {
+                return Arc::new(tls.channel_factory());
+            }
+            Arc::new(DefaultTonicChannelFactory::new())
+        });
         let client = Arc::new(CouncilClient {
-            tonic_channel_factory: Arc::clone(&self.tonic_channel_factory),
+            tonic_channel_factory: Arc::clone(&tonic_channel_factory),
+            cluster_name: self.cluster_name.clone(),
+            keyring: self.keyring.clone(),
+            this_node_advertised_url: cluster.this_advertised_url.clone(),
+            join_token: self.join_token,
         });
 
         let main_thread = tokio::spawn(Council::main_thread(
@@ -100,16 +326,22 @@ impl CouncilBuilder {
             cluster,
             message_receiver,
             message_sender.clone(),
-            cluster_events_sender.clone(),
+            event_publisher.clone(),
+            cluster_sender,
             client,
         ));
 
         Council {
             this_node_id: self.this_node_id,
-            cluster_events_sender,
-            tonic_channel_factory: self.tonic_channel_factory,
+            cluster_name: self.cluster_name,
+            keyring: self.keyring,
+            #[cfg(feature = "tls")]
+            tls: self.tls,
+            tonic_channel_factory,
             main_thread_message_sender: message_sender,
-            _main_thread: main_thread,
+            event_publisher,
+            cluster_receiver,
+            main_thread,
         }
     }
 }
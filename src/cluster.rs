@@ -1,15 +1,36 @@
-use std::{collections::HashSet, time::Instant};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use url::Url;
 
-use self::{failure_detector::FailureDetector, views::ClusterView};
-use crate::node::NodeId;
+use self::{
+    admission::{AdmissionDenials, AdmissionHook},
+    bootstrap::Bootstrap,
+    downing::DowningStrategy,
+    failure_detector::FailureDetector,
+    swim::Swim,
+    views::{ClusterView, MemberView},
+};
+use crate::node::{NodeId, NodeStatus};
 
+pub mod admission;
+pub mod bootstrap;
+pub mod convergence;
+pub mod downing;
 pub mod failure_detector;
+pub mod reachability;
+pub mod swim;
 pub mod version_vector;
 pub mod views;
 
+pub(crate) mod digest;
 mod gossip_destinations;
+mod heartbeat;
+mod leader;
+mod removal;
 
 /// Represents the state of the cluster
 #[cfg_attr(feature = "serde", derive(serde::Serialize))]
@@ -20,27 +41,165 @@ pub struct Cluster {
     pub cluster_view: ClusterView,
     pub peer_nodes: HashSet<Url>,
     pub unknwon_peer_nodes: HashSet<Url>,
-    pub failure_detector: FailureDetector,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub failure_detector: Box<dyn FailureDetector>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) downing_strategy: Option<Arc<dyn DowningStrategy>>,
+    pub(crate) downing_stable_after: Duration,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) unreachable_since: HashMap<NodeId, Instant>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) unreachable_members_changed_at: Instant,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) bootstrap: Option<Bootstrap>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) swim: Option<Swim>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) suspected_since: HashMap<NodeId, Instant>,
+    pub(crate) heartbeat_interval: Duration,
+    pub(crate) monitored_by_nr_of_members: usize,
+    pub(crate) tombstone_ttl: Duration,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) admission_hook: Option<Arc<dyn AdmissionHook>>,
+    pub(crate) admission_denials: AdmissionDenials,
 }
 
 impl Cluster {
-    pub fn has_converged(&self) -> bool {
+    /// Tells whether the running node considers a member to be reachable, that is the failure detector considers
+    /// it live and it hasn't been [suspected](swim) of having failed for longer than the suspicion timeout.
+    /// The running node always considers itself reachable.
+    pub fn is_reachable(&self, node_id: NodeId, now: Instant) -> bool {
+        node_id == self.this_node_id
+            || (self.failure_detector.is_live(node_id, now)
+                && !self.suspicion_has_expired(node_id, now))
+    }
+
+    fn is_suspected(&self, node_id: NodeId) -> bool {
+        self.cluster_view
+            .known_members
+            .get(&node_id)
+            .and_then(|m| m.state.as_ref())
+            .is_some_and(|s| s.suspected)
+    }
+
+    /// Returns the leader of the cluster, as seen by the running node.
+    ///
+    /// The leader is the member with the lowest [NodeId] among the reachable members whose status is
+    /// [NodeStatus::Joining], [NodeStatus::Up] or [NodeStatus::Leaving]. The choice is deterministic: once the cluster
+    /// has converged, every node agrees on the same leader.
+    pub fn leader(&self) -> Option<NodeId> {
         let now = Instant::now();
-        let all_members_ids: HashSet<NodeId> =
-            self.cluster_view.known_members.keys().cloned().collect();
-
-        let all_members_are_live_and_converged =
-            self.cluster_view.known_members.iter().all(|(_, member)| {
-                let member_is_live =
-                    member.id == self.this_node_id || self.failure_detector.is_live(member.id, now);
-                let member_observed_all_states = member
-                    .state
+        self.cluster_view
+            .known_members
+            .values()
+            .filter(|member| {
+                member.state.as_ref().is_some_and(|state| {
+                    matches!(
+                        state.node_status,
+                        NodeStatus::Joining | NodeStatus::Up | NodeStatus::Leaving
+                    )
+                })
+            })
+            .map(|member| member.id)
+            .filter(|id| self.is_reachable(*id, now))
+            .min()
+    }
+
+    /// Tells whether the running node is the leader of the cluster
+    pub fn is_leader(&self) -> bool {
+        self.leader() == Some(self.this_node_id)
+    }
+
+    /// Returns the known members that have declared the given role, whatever their status
+    pub fn members_with_role<'a>(
+        &'a self,
+        role: &'a str,
+    ) -> impl Iterator<Item = &'a MemberView> + 'a {
+        self.cluster_view
+            .known_members
+            .values()
+            .filter(move |m| m.has_role(role))
+    }
+
+    /// Returns the members that are [NodeStatus::Up] and have declared the given role
+    pub fn up_members_with_role<'a>(
+        &'a self,
+        role: &'a str,
+    ) -> impl Iterator<Item = &'a MemberView> + 'a {
+        self.members_with_role(role).filter(|m| {
+            m.state
+                .as_ref()
+                .is_some_and(|s| s.node_status == NodeStatus::Up)
+        })
+    }
+
+    /// Tells whether every member of the cluster supports a feature of the gossip [protocol](crate::protocol).
+    /// Removed members no longer count.
+    pub fn all_members_support(&self, feature: &str) -> bool {
+        self.cluster_view
+            .known_members
+            .values()
+            .filter(|m| {
+                m.state
                     .as_ref()
-                    .map_or(false, |state| state.observed_by == all_members_ids);
-                member_is_live && member_observed_all_states
-            });
+                    .is_none_or(|s| s.node_status != NodeStatus::Removed)
+            })
+            .all(|m| m.protocol.supports(feature))
+    }
+
+    /// Publishes an entry of the application state of the running node.
+    /// Returns true if the entry has changed.
+    pub(crate) fn set_local_state(&mut self, key: String, value: String) -> bool {
+        self.cluster_view
+            .known_members
+            .get_mut(&self.this_node_id)
+            .is_some_and(|m| m.set_application_state(key, value))
+    }
+
+    /// Marks the running node as [NodeStatus::Leaving], provided it is either joining or up.
+    /// Returns true if the status of the running node has changed.
+    pub(crate) fn mark_own_node_as_leaving(&mut self) -> bool {
+        let own_status = self
+            .cluster_view
+            .known_members
+            .get(&self.this_node_id)
+            .and_then(|m| m.state.as_ref())
+            .map(|s| s.node_status);
 
-        self.unknwon_peer_nodes.is_empty() && all_members_are_live_and_converged
+        match own_status {
+            Some(NodeStatus::Joining | NodeStatus::Up) => {
+                log::info!("[Node id: {}] Leaving the cluster", self.this_node_id);
+                self.cluster_view.update_member_status(
+                    self.this_node_id,
+                    self.this_node_id,
+                    NodeStatus::Leaving,
+                )
+            }
+            _ => false,
+        }
+    }
+
+    /// Tells whether the running node has left the cluster, that is if it has been moved to
+    /// [NodeStatus::Exiting] or [NodeStatus::Down], and every known member has observed it,
+    /// or if it has been [removed](removal) from the cluster.
+    pub(crate) fn has_left(&self) -> bool {
+        if self.is_removed(self.this_node_id) {
+            return true;
+        }
+        self.cluster_view
+            .known_members
+            .get(&self.this_node_id)
+            .and_then(|m| m.state.as_ref())
+            .is_some_and(|state| {
+                matches!(
+                    state.node_status,
+                    NodeStatus::Exiting | NodeStatus::Down | NodeStatus::Removed
+                ) && self
+                    .cluster_view
+                    .known_members
+                    .keys()
+                    .all(|id| state.observed_by.contains(id))
+            })
     }
 
     /// Increments the heartbeat of the running node by one and returns the new value
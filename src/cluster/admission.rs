@@ -0,0 +1,223 @@
+//! Admission control: deciding which nodes may join the cluster.
+//!
+//! By default, any node that gossips with the running node becomes a member. An [AdmissionHook] lets the
+//! application turn nodes away, based on their URL, their roles, their application state or the
+//! [join token](crate::CouncilBuilder::with_join_token) they present. The hook is consulted:
+//! - when a node the running node has never heard of shows up in an incoming view. Denied nodes are ignored,
+//!   along with the views they send, and are told why on their next gossip exchange.
+//!   Join tokens are never gossiped: a node only presents its token along with the views it sends itself, so that
+//!   nodes hearing of it through other members can only rely on these members having admitted it
+//! - when the leader would move a joining member to [NodeStatus::Up]. Members denied at this point are already known
+//!   to the cluster, so the leader downs them instead
+//!
+//! Denials are forgotten after [ADMISSION_DENIAL_TTL], after which the denied nodes are considered anew.
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    time::{Duration, SystemTime},
+};
+
+use super::{
+    views::{MemberView, PartialClusterView},
+    Cluster,
+};
+use crate::node::{NodeId, NodeStatus};
+
+/// How long the running node remembers that it has denied a node admission
+pub const ADMISSION_DENIAL_TTL: Duration = Duration::from_secs(300);
+/// How many denials the running node remembers at most, so that a flood of denied nodes can't exhaust its memory
+const MAX_ADMISSION_DENIALS: usize = 1024;
+
+/// Decides whether a node may join the cluster, see the [module documentation](self)
+pub trait AdmissionHook: Debug + Send + Sync {
+    /// The join token is the one the candidate has presented to the running node, which it only does at the
+    /// [AdmissionStage::Discovered] stage
+    fn admit(
+        &self,
+        stage: AdmissionStage,
+        candidate: &MemberView,
+        join_token: Option<&str>,
+    ) -> Admission;
+}
+
+/// When an [AdmissionHook] is consulted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdmissionStage {
+    /// The running node has just heard of the candidate for the first time, from the candidate itself
+    Discovered,
+    /// The running node has just heard of the candidate for the first time, from a member that has admitted it
+    Relayed,
+    /// The running node leads the cluster, and is about to move the candidate to [NodeStatus::Up]
+    MovingUp,
+}
+
+/// The decision of an [AdmissionHook]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Admission {
+    Accept,
+    /// Denies the candidate. The reason is logged, and reported back to the candidate.
+    Deny(String),
+}
+
+/// Admits the nodes presenting one of the given join tokens.
+/// Nodes relayed by other members have presented their token to these members, so they are admitted.
+#[derive(Debug, Clone)]
+pub struct RequireJoinToken {
+    pub tokens: Vec<String>,
+}
+
+impl AdmissionHook for RequireJoinToken {
+    fn admit(
+        &self,
+        stage: AdmissionStage,
+        _candidate: &MemberView,
+        join_token: Option<&str>,
+    ) -> Admission {
+        if stage != AdmissionStage::Discovered {
+            return Admission::Accept;
+        }
+        match join_token {
+            Some(token) if self.tokens.iter().any(|t| t == token) => Admission::Accept,
+            Some(_) => Admission::Deny("invalid join token".to_string()),
+            None => Admission::Deny("missing join token".to_string()),
+        }
+    }
+}
+
+/// Why and when an [AdmissionHook] has denied a node admission
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Clone)]
+pub(crate) struct AdmissionDenial {
+    reason: String,
+    denied_at: SystemTime,
+}
+
+/// The nodes an [AdmissionHook] has denied
+pub(crate) type AdmissionDenials = HashMap<NodeId, AdmissionDenial>;
+
+/// The error returned to a node that has been denied admission
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("admission denied: {0}")]
+pub struct AdmissionDenied(pub String);
+
+impl Cluster {
+    /// Returns why a node has been denied admission by the running node, if it has
+    pub fn admission_denial(&self, node_id: NodeId) -> Option<&str> {
+        self.admission_denials
+            .get(&node_id)
+            .map(|denial| denial.reason.as_str())
+    }
+
+    /// Removes from an incoming view the nodes that are denied admission.
+    /// The sender of the view is considered first, with the join token it presents: the other nodes are only relayed.
+    /// Returns false if the sender of the view is itself denied, in which case the whole view must be ignored.
+    pub(crate) fn filter_admitted_members(
+        &mut self,
+        incoming: &mut PartialClusterView,
+        now: SystemTime,
+    ) -> bool {
+        let mut candidates: Vec<NodeId> = incoming
+            .members
+            .keys()
+            .filter(|id| {
+                !self.cluster_view.known_members.contains_key(id) && !self.is_removed(**id)
+            })
+            .cloned()
+            .collect();
+        candidates.sort_by_key(|id| *id != incoming.this_node_id);
+        for node_id in candidates {
+            let (stage, join_token) = if node_id == incoming.this_node_id {
+                (AdmissionStage::Discovered, incoming.join_token.as_deref())
+            } else {
+                (AdmissionStage::Relayed, None)
+            };
+            let admitted = !self.admission_denials.contains_key(&node_id)
+                && self.admit(stage, &incoming.members[&node_id], join_token, now);
+            if !admitted {
+                incoming.members.remove(&node_id);
+            }
+        }
+        !self.admission_denials.contains_key(&incoming.this_node_id)
+    }
+
+    /// Consults the [AdmissionHook], if any, about the member the leader is about to move up.
+    /// Returns the status the member should be moved to instead: denied members are downed.
+    pub(crate) fn status_after_admission(
+        &mut self,
+        node_id: NodeId,
+        now: SystemTime,
+    ) -> NodeStatus {
+        let admitted = match self.cluster_view.known_members.get(&node_id) {
+            Some(member) if node_id != self.this_node_id => {
+                self.admit(AdmissionStage::MovingUp, &member.clone(), None, now)
+            }
+            _ => true,
+        };
+        if admitted {
+            NodeStatus::Up
+        } else {
+            NodeStatus::Down
+        }
+    }
+
+    /// Consults the [AdmissionHook], and records the reason of any denial
+    fn admit(
+        &mut self,
+        stage: AdmissionStage,
+        candidate: &MemberView,
+        join_token: Option<&str>,
+        now: SystemTime,
+    ) -> bool {
+        let admission = self
+            .admission_hook
+            .as_ref()
+            .map_or(Admission::Accept, |hook| {
+                hook.admit(stage, candidate, join_token)
+            });
+        match admission {
+            Admission::Accept => true,
+            Admission::Deny(reason) => {
+                log::warn!(
+                    "[Node id: {}] Denying admission to {} ({}): {}",
+                    self.this_node_id,
+                    candidate.id,
+                    candidate.advertised_addr,
+                    reason
+                );
+                self.record_admission_denial(candidate.id, reason, now);
+                false
+            }
+        }
+    }
+
+    /// Records a denial, making room for it by forgetting the oldest one when too many are remembered
+    fn record_admission_denial(&mut self, node_id: NodeId, reason: String, now: SystemTime) {
+        self.expire_admission_denials(now);
+        if self.admission_denials.len() >= MAX_ADMISSION_DENIALS {
+            let oldest = self
+                .admission_denials
+                .iter()
+                .min_by_key(|(_, denial)| denial.denied_at)
+                .map(|(node_id, _)| *node_id);
+            if let Some(oldest) = oldest {
+                self.admission_denials.remove(&oldest);
+            }
+        }
+        self.admission_denials.insert(
+            node_id,
+            AdmissionDenial {
+                reason,
+                denied_at: now,
+            },
+        );
+    }
+
+    /// Forgets the denials older than [ADMISSION_DENIAL_TTL]
+    pub(crate) fn expire_admission_denials(&mut self, now: SystemTime) {
+        // Denials from the future, if the clock went back, are kept until the clock catches up
+        self.admission_denials.retain(|_, denial| {
+            !now.duration_since(denial.denied_at)
+                .is_ok_and(|age| age >= ADMISSION_DENIAL_TTL)
+        });
+    }
+}
@@ -0,0 +1,167 @@
+//! Forming a new cluster when no existing cluster can be found, in the spirit of Akka Cluster Bootstrap.
+//!
+//! A bootstrapping node doesn't join anyone right away. Instead it regularly probes a set of contact points,
+//! which are usually the other nodes being deployed along with it:
+//! - as soon as one contact point reports that it is part of a formed cluster, the node joins that cluster
+//! - otherwise, once enough contact points have answered and the set of answering contact points has remained
+//!   the same for a while, the contact point with the lowest advertised URL joins itself and becomes the first
+//!   [Up](NodeStatus::Up) member of a new cluster. The other nodes then find it through their probes and join it.
+//!
+//! While bootstrapping, the leader doesn't move [Joining](NodeStatus::Joining) members up unless the cluster
+//! already has an up member, so that nodes discovering each other through gossip can't form a cluster of their own.
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::{Duration, Instant},
+};
+
+use url::Url;
+
+use super::Cluster;
+use crate::node::NodeStatus;
+
+/// Configures how a node looks for an existing cluster, and decides to form a new one
+#[derive(Debug, Clone)]
+pub struct BootstrapSettings {
+    /// The nodes to probe. The running node may be part of the contact points.
+    pub contact_points: Vec<Url>,
+    /// How many contact points, including the running node if it is one of them, must have answered
+    /// before a new cluster can be formed. Defaults to the number of contact points.
+    pub required_contact_points: usize,
+    /// How long the set of answering contact points must remain unchanged before a new cluster can be formed.
+    /// Defaults to 5 seconds.
+    pub stable_margin: Duration,
+}
+
+impl BootstrapSettings {
+    pub fn new(contact_points: impl IntoIterator<Item = Url>) -> Self {
+        let contact_points: Vec<Url> = contact_points.into_iter().collect();
+        Self {
+            required_contact_points: contact_points.len(),
+            contact_points,
+            stable_margin: Duration::from_secs(5),
+        }
+    }
+    pub fn with_required_contact_points(mut self, required_contact_points: usize) -> Self {
+        self.required_contact_points = required_contact_points;
+        self
+    }
+    pub fn with_stable_margin(mut self, stable_margin: Duration) -> Self {
+        self.stable_margin = stable_margin;
+        self
+    }
+}
+
+/// The progress of the bootstrap of the running node
+#[derive(Debug, Clone)]
+pub(crate) enum Bootstrap {
+    Probing {
+        settings: BootstrapSettings,
+        answering_contact_points: BTreeSet<Url>,
+        answering_contact_points_changed_at: Instant,
+    },
+    /// The running node has either joined an existing cluster, or formed a new one
+    Completed,
+}
+
+impl Bootstrap {
+    pub(crate) fn new(settings: BootstrapSettings) -> Self {
+        Bootstrap::Probing {
+            settings,
+            answering_contact_points: BTreeSet::new(),
+            answering_contact_points_changed_at: Instant::now(),
+        }
+    }
+}
+
+impl Cluster {
+    /// Tells whether the running node is part of a formed cluster, that is a cluster with at least one up member
+    pub fn is_formed(&self) -> bool {
+        self.cluster_view.known_members.values().any(|m| {
+            m.state
+                .as_ref()
+                .is_some_and(|s| s.node_status == NodeStatus::Up)
+        })
+    }
+
+    /// Returns the contact points to probe, unless the running node is done bootstrapping
+    pub(crate) fn contact_points_to_probe(&self) -> Vec<Url> {
+        match &self.bootstrap {
+            Some(Bootstrap::Probing { settings, .. }) => settings
+                .contact_points
+                .iter()
+                .filter(|url| **url != self.this_advertised_url)
+                .cloned()
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Handles the answers to a round of probes, each answer telling whether the contact point
+    /// is part of a formed cluster. Contact points that didn't answer are absent from `answers`.
+    /// Returns true if the running node has joined or formed a cluster.
+    pub(crate) fn handle_probe_answers(
+        &mut self,
+        answers: HashMap<Url, bool>,
+        now: Instant,
+    ) -> bool {
+        let Some(Bootstrap::Probing {
+            settings,
+            answering_contact_points,
+            answering_contact_points_changed_at,
+        }) = &mut self.bootstrap
+        else {
+            return false;
+        };
+
+        let formed_cluster_contact_points: Vec<Url> = answers
+            .iter()
+            .filter(|(_, cluster_formed)| **cluster_formed)
+            .map(|(url, _)| url.clone())
+            .collect();
+        if !formed_cluster_contact_points.is_empty() {
+            log::info!(
+                "[Node id: {}] Found an existing cluster through contact points {:?}, joining it",
+                self.this_node_id,
+                formed_cluster_contact_points
+            );
+            self.peer_nodes
+                .extend(formed_cluster_contact_points.iter().cloned());
+            self.unknwon_peer_nodes
+                .extend(formed_cluster_contact_points);
+            self.bootstrap = Some(Bootstrap::Completed);
+            return true;
+        }
+
+        let mut answering: BTreeSet<Url> = answers.into_keys().collect();
+        if settings.contact_points.contains(&self.this_advertised_url) {
+            answering.insert(self.this_advertised_url.clone());
+        }
+        if answering != *answering_contact_points {
+            *answering_contact_points = answering;
+            *answering_contact_points_changed_at = now;
+            return false;
+        }
+
+        let is_stable = answering_contact_points.len() >= settings.required_contact_points
+            && now.duration_since(*answering_contact_points_changed_at) >= settings.stable_margin;
+        let has_lowest_url = answering_contact_points.first() == Some(&self.this_advertised_url);
+        if !is_stable || !has_lowest_url {
+            return false;
+        }
+
+        log::info!(
+            "[Node id: {}] No existing cluster found among {} contact points, forming a new cluster",
+            self.this_node_id,
+            answering_contact_points.len()
+        );
+        self.bootstrap = Some(Bootstrap::Completed);
+        self.cluster_view
+            .update_member_status(self.this_node_id, self.this_node_id, NodeStatus::Up)
+    }
+
+    /// Tells whether the leader is allowed to move joining members up.
+    /// While bootstrapping, only a cluster that has already been formed can accept new members.
+    pub(crate) fn can_move_joining_members_up(&self) -> bool {
+        self.bootstrap.is_none() || self.is_formed()
+    }
+}
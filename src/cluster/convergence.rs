@@ -0,0 +1,109 @@
+//! Gossip convergence, in the sense of Akka Cluster.
+//!
+//! The cluster has converged when every member that matters has observed the latest state of every member.
+//! Members that are [Exiting](NodeStatus::Exiting), [Down](NodeStatus::Down) or [Removed](NodeStatus::Removed)
+//! are on their way out, so they don't
+//! need to observe anything. Unreachable members can't observe anything either, so they are left out as well,
+//! but an unreachable member that is neither down nor exiting prevents convergence until it becomes reachable again
+//! or is downed, e.g. by a [downing strategy](super::downing). Members that have restarted are the exception: the
+//! leader downs their previous incarnation once the new one is up, which requires convergence in the first place.
+use std::{collections::HashSet, time::Instant};
+
+use url::Url;
+
+use super::Cluster;
+use crate::node::{NodeId, NodeStatus};
+
+/// The reason why the cluster hasn't converged yet, see [Cluster::convergence_blockers]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ConvergenceBlocker {
+    /// A peer node the running node was configured with hasn't sent its view yet
+    UnknownPeer(Url),
+    /// A member is unreachable, but neither down nor exiting
+    Unreachable(NodeId),
+    /// A member hasn't observed the latest state of another member yet
+    NotObserved { observer: NodeId, member: NodeId },
+}
+
+impl Cluster {
+    /// Tells whether the cluster has converged, that is whether the leader is allowed to change the status of members.
+    /// See the [module documentation](self) for more details.
+    pub fn has_converged(&self) -> bool {
+        self.convergence_blockers().is_empty()
+    }
+
+    /// Returns everything that prevents the cluster from converging. Only meant for diagnostics.
+    pub fn convergence_blockers(&self) -> Vec<ConvergenceBlocker> {
+        let now = Instant::now();
+        let mut blockers: Vec<ConvergenceBlocker> = self
+            .unknwon_peer_nodes
+            .iter()
+            .cloned()
+            .map(ConvergenceBlocker::UnknownPeer)
+            .collect();
+
+        let members_in_the_cluster: HashSet<NodeId> = self
+            .cluster_view
+            .known_members
+            .values()
+            .filter(|m| {
+                !m.state.as_ref().is_some_and(|s| {
+                    matches!(
+                        s.node_status,
+                        NodeStatus::Exiting | NodeStatus::Down | NodeStatus::Removed
+                    )
+                })
+            })
+            .map(|m| m.id)
+            .collect();
+        let mut observers = HashSet::new();
+        for node_id in &members_in_the_cluster {
+            if self.is_unreachable_from(&members_in_the_cluster, *node_id, now) {
+                if !self.has_restarted(*node_id) {
+                    blockers.push(ConvergenceBlocker::Unreachable(*node_id));
+                }
+            } else {
+                observers.insert(*node_id);
+            }
+        }
+
+        let mut not_observed: Vec<ConvergenceBlocker> = self
+            .cluster_view
+            .known_members
+            .values()
+            .flat_map(|member| {
+                observers
+                    .iter()
+                    .filter(move |observer| {
+                        !member
+                            .state
+                            .as_ref()
+                            .is_some_and(|s| s.observed_by.contains(observer))
+                    })
+                    .map(move |observer| ConvergenceBlocker::NotObserved {
+                        observer: *observer,
+                        member: member.id,
+                    })
+            })
+            .collect();
+        blockers.append(&mut not_observed);
+        blockers
+    }
+
+    /// Tells whether the running node, or any of the given observers in the [reachability table](super::reachability),
+    /// considers a member unreachable. Observers that have left the cluster may never update their records again,
+    /// so they are not taken into account.
+    fn is_unreachable_from(
+        &self,
+        observers: &HashSet<NodeId>,
+        node_id: NodeId,
+        now: Instant,
+    ) -> bool {
+        !self.is_reachable(node_id, now)
+            || !self
+                .cluster_view
+                .reachability
+                .unreachable_by(node_id)
+                .is_disjoint(observers)
+    }
+}
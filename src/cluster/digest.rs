@@ -0,0 +1,157 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
+
+use super::{
+    version_vector::{VersionVector, VersionVectorOffset},
+    views::{MemberViewState, PartialClusterView},
+    Cluster,
+};
+use crate::node::NodeId;
+
+/// A compact summary of a [ClusterView](super::views::ClusterView), sent before any member view is exchanged.
+///
+/// Comparing a digest with its own view lets a node tell which [member views](super::views::MemberView) its peer is
+/// missing or lagging behind on, so that only those are shipped instead of the whole view.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ClusterDigest {
+    pub(crate) this_node_id: NodeId,
+    pub(crate) version_vector: VersionVector,
+    pub(crate) heartbeats: HashMap<NodeId, u64>,
+    /// The highest version among the application state entries of each member
+    pub(crate) application_state_versions: HashMap<NodeId, u64>,
+    /// A digest of the status and observers of each member, see [MemberViewState::observations_digest].
+    /// Observers can change without a version bump, which the version vector alone would miss.
+    pub(crate) observations_digests: HashMap<NodeId, u64>,
+    /// The highest version among the [reachability records](super::reachability) of each observer
+    pub(crate) reachability_versions: HashMap<NodeId, u64>,
+    /// The members the sender has [removed](super::removal)
+    pub(crate) tombstones: HashSet<NodeId>,
+    /// The members the sender has [quarantined](super::views::ClusterView::quarantine)
+    pub(crate) quarantine: HashSet<NodeId>,
+}
+
+impl Cluster {
+    /// Summarizes the running node's view of the cluster
+    pub(crate) fn digest(&self) -> ClusterDigest {
+        let members = self.cluster_view.known_members.values();
+        ClusterDigest {
+            this_node_id: self.this_node_id,
+            version_vector: self.cluster_view.version_vector.clone(),
+            heartbeats: self.cluster_view.heartbeats.clone(),
+            application_state_versions: members
+                .clone()
+                .filter(|m| !m.application_state.is_empty())
+                .map(|m| (m.id, m.application_state_version()))
+                .collect(),
+            observations_digests: members
+                .filter_map(|m| Some((m.id, m.state.as_ref()?.observations_digest())))
+                .collect(),
+            reachability_versions: self.cluster_view.reachability.observer_versions(),
+            tombstones: self.cluster_view.tombstones.keys().cloned().collect(),
+            quarantine: self.cluster_view.quarantine.clone(),
+        }
+    }
+
+    /// Returns the whole view of the running node, for the peers that don't gossip digests
+    pub(crate) fn full_view(&self) -> PartialClusterView {
+        PartialClusterView {
+            this_node_id: self.this_node_id,
+            members: self.cluster_view.known_members.clone(),
+            reachability: self.reachability().records().copied().collect(),
+            tombstones: self.cluster_view.tombstones.clone(),
+            quarantine: self.cluster_view.quarantine.clone(),
+            join_token: None,
+        }
+    }
+
+    /// Returns the member views, reachability records, tombstones and quarantined members the sender of `digest` is missing
+    /// or lagging behind on
+    pub(crate) fn delta_for(&self, digest: &ClusterDigest) -> PartialClusterView {
+        let mut behind =
+            VersionVectorOffset::of(&self.cluster_view.version_vector, &digest.version_vector)
+                .behind_lhs;
+        for member in self.cluster_view.known_members.values() {
+            let application_state_behind = digest
+                .application_state_versions
+                .get(&member.id)
+                .copied()
+                .unwrap_or(0)
+                < member.application_state_version();
+            let observations_differ = member.state.as_ref().is_some_and(|state| {
+                digest.observations_digests.get(&member.id) != Some(&state.observations_digest())
+            });
+            if application_state_behind || observations_differ {
+                behind.insert(member.id);
+            }
+        }
+
+        let reachability = &self.cluster_view.reachability;
+        PartialClusterView {
+            this_node_id: self.this_node_id,
+            members: behind
+                .into_iter()
+                .filter_map(|id| Some((id, self.cluster_view.known_members.get(&id)?.clone())))
+                .collect(),
+            reachability: reachability
+                .observer_versions()
+                .into_iter()
+                .filter(|(observer, version)| {
+                    digest
+                        .reachability_versions
+                        .get(observer)
+                        .copied()
+                        .unwrap_or(0)
+                        < *version
+                })
+                .flat_map(|(observer, _)| reachability.records_of(observer).copied())
+                .collect(),
+            tombstones: self
+                .cluster_view
+                .tombstones
+                .iter()
+                .filter(|(node_id, _)| !digest.tombstones.contains(node_id))
+                .map(|(node_id, removed_at)| (*node_id, *removed_at))
+                .collect(),
+            quarantine: self
+                .cluster_view
+                .quarantine
+                .difference(&digest.quarantine)
+                .cloned()
+                .collect(),
+            join_token: None,
+        }
+    }
+
+    /// Records the heartbeats carried by a digest. Heartbeats change on every gossip round,
+    /// so they travel in digests rather than in member views.
+    pub(crate) fn record_digest_heartbeats(&mut self, digest: &ClusterDigest) {
+        let now = Instant::now();
+        for (node_id, heartbeat) in &digest.heartbeats {
+            self.record_heartbeat(*node_id, *heartbeat, now);
+        }
+    }
+}
+
+impl MemberViewState {
+    /// Hashes the status, the liveness and the observers of this state into a value that doesn't depend on the
+    /// iteration order of the observers, so that two nodes with the same state compute the same digest.
+    pub(crate) fn observations_digest(&self) -> u64 {
+        let liveness = mix((self.incarnation << 1) | self.suspected as u64);
+        self.observed_by
+            .iter()
+            .map(|id| mix(id.unique_id ^ id.generation.rotate_left(32)))
+            .fold(
+                mix(self.node_status as u64) ^ liveness.rotate_left(17),
+                |acc, h| acc ^ h,
+            )
+    }
+}
+
+/// The SplitMix64 finalizer, a cheap way to spread the bits of a value
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
@@ -0,0 +1,472 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    time::{Duration, Instant},
+};
+
+use super::{views::MemberView, Cluster};
+use crate::node::{NodeId, NodeStatus};
+
+/// A [DowningStrategy] decides which members should be marked as [NodeStatus::Down] when some
+/// members of the cluster have become unreachable.
+///
+/// Strategies are only consulted by the leader, once the set of unreachable members has been stable for
+/// a configurable period (see [CouncilBuilder::with_downing_stable_after](crate::CouncilBuilder::with_downing_stable_after)).
+/// Since every side of a network partition elects its own leader, a strategy must be written so that
+/// both sides reach complementary decisions, e.g. one side downs the other and the other downs itself.
+pub trait DowningStrategy: Debug + Send + Sync {
+    /// Returns the members that should be marked as [NodeStatus::Down]
+    fn decide(&self, partition: &Partition) -> HashSet<NodeId>;
+}
+
+/// How the running node sees the cluster when a downing decision has to be made.
+///
+/// Only members that are neither [NodeStatus::Exiting], [NodeStatus::Down] nor [NodeStatus::Removed] are taken into account.
+#[derive(Debug)]
+pub struct Partition<'a> {
+    pub this_node_id: NodeId,
+    /// Members that the running node can reach, including itself
+    pub reachable: HashSet<NodeId>,
+    /// Members that the running node cannot reach, along with how long they have been unreachable
+    pub unreachable: HashMap<NodeId, Duration>,
+    pub members: &'a HashMap<NodeId, MemberView>,
+}
+
+impl<'a> Partition<'a> {
+    fn all_members(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.reachable
+            .iter()
+            .chain(self.unreachable.keys())
+            .cloned()
+    }
+
+    fn unreachable_members(&self) -> HashSet<NodeId> {
+        self.unreachable.keys().cloned().collect()
+    }
+}
+
+/// Downs every member that has been unreachable for longer than the given timeout.
+///
+/// This strategy doesn't protect against network partitions: both sides of a partition
+/// will down each other, effectively forming two separate clusters.
+#[derive(Debug, Clone)]
+pub struct AutoDownAfterTimeout {
+    pub timeout: Duration,
+}
+
+impl DowningStrategy for AutoDownAfterTimeout {
+    fn decide(&self, partition: &Partition) -> HashSet<NodeId> {
+        partition
+            .unreachable
+            .iter()
+            .filter(|(_, unreachable_for)| **unreachable_for >= self.timeout)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+}
+
+/// Keeps the side of the partition that contains the majority of the members, and downs the other side.
+/// When both sides have the same size, the side that contains the member with the lowest [NodeId] is kept.
+#[derive(Debug, Clone, Default)]
+pub struct KeepMajority;
+
+impl DowningStrategy for KeepMajority {
+    fn decide(&self, partition: &Partition) -> HashSet<NodeId> {
+        let reachable_count = partition.reachable.len();
+        let unreachable_count = partition.unreachable.len();
+        let keep_reachable_side = match reachable_count.cmp(&unreachable_count) {
+            std::cmp::Ordering::Greater => true,
+            std::cmp::Ordering::Less => false,
+            std::cmp::Ordering::Equal => partition
+                .all_members()
+                .min()
+                .is_some_and(|lowest| partition.reachable.contains(&lowest)),
+        };
+
+        if keep_reachable_side {
+            partition.unreachable_members()
+        } else {
+            partition.reachable.clone()
+        }
+    }
+}
+
+/// Keeps the side of the partition that contains at least `quorum_size` members, and downs the other side.
+/// If the reachable side doesn't meet the quorum, it downs itself.
+///
+/// The quorum size should be greater than half the expected size of the cluster, and the cluster
+/// should not grow beyond `quorum_size * 2 - 1` members, otherwise both sides may meet the quorum.
+#[derive(Debug, Clone)]
+pub struct StaticQuorum {
+    pub quorum_size: usize,
+}
+
+impl DowningStrategy for StaticQuorum {
+    fn decide(&self, partition: &Partition) -> HashSet<NodeId> {
+        if partition.reachable.len() >= self.quorum_size {
+            partition.unreachable_members()
+        } else {
+            partition.reachable.clone()
+        }
+    }
+}
+
+/// Keeps the side of the partition that contains the oldest member, and downs the other side.
+///
+/// The oldest member is the one that was started first, i.e. the one with the lowest [NodeId::generation], whether
+/// it counts seconds or nanoseconds.
+/// When `down_if_alone` is true and the oldest member is alone on its side, the oldest member is downed instead,
+/// so that a single faulty node cannot bring down the rest of the cluster.
+#[derive(Debug, Clone, Default)]
+pub struct KeepOldest {
+    pub down_if_alone: bool,
+}
+
+impl DowningStrategy for KeepOldest {
+    fn decide(&self, partition: &Partition) -> HashSet<NodeId> {
+        let oldest = match partition
+            .all_members()
+            .min_by_key(|id| (id.incarnation(), id.unique_id))
+        {
+            Some(oldest) => oldest,
+            None => return HashSet::new(),
+        };
+
+        if partition.reachable.contains(&oldest) {
+            if self.down_if_alone
+                && partition.reachable.len() == 1
+                && !partition.unreachable.is_empty()
+            {
+                HashSet::from([oldest])
+            } else {
+                partition.unreachable_members()
+            }
+        } else if self.down_if_alone && partition.unreachable.len() == 1 {
+            HashSet::from([oldest])
+        } else {
+            partition.reachable.clone()
+        }
+    }
+}
+
+impl Cluster {
+    /// Returns the moment the running node started to consider a member unreachable,
+    /// or None if the member is reachable.
+    pub fn unreachable_since(&self, node_id: NodeId) -> Option<Instant> {
+        self.unreachable_since.get(&node_id).cloned()
+    }
+
+    /// Keeps track of when members became unreachable, and when the set of unreachable members last changed
+    fn track_unreachable_members(&mut self, now: Instant) {
+        let unreachable: HashSet<NodeId> = self
+            .cluster_view
+            .known_members
+            .values()
+            .filter(|m| {
+                m.state
+                    .as_ref()
+                    .is_some_and(|s| is_considered_for_downing(s.node_status))
+            })
+            .map(|m| m.id)
+            .filter(|id| !self.is_reachable(*id, now))
+            .collect();
+
+        let previous_count = self.unreachable_since.len();
+        self.unreachable_since
+            .retain(|id, _| unreachable.contains(id));
+        let mut changed = self.unreachable_since.len() != previous_count;
+        for id in unreachable {
+            self.unreachable_since.entry(id).or_insert_with(|| {
+                changed = true;
+                now
+            });
+        }
+
+        if changed {
+            self.unreachable_members_changed_at = now;
+        }
+    }
+
+    /// Applies the configured [DowningStrategy], provided the running node is the leader and the set
+    /// of unreachable members has been stable for long enough.
+    /// Returns true if at least one member has been downed.
+    pub(crate) fn perform_downing(&mut self, now: Instant) -> bool {
+        self.track_unreachable_members(now);
+
+        let strategy = match &self.downing_strategy {
+            Some(strategy) => strategy,
+            None => return false,
+        };
+        if self.unreachable_since.is_empty()
+            || now.duration_since(self.unreachable_members_changed_at) < self.downing_stable_after
+            || !self.is_leader()
+        {
+            return false;
+        }
+
+        let reachable = self
+            .cluster_view
+            .known_members
+            .values()
+            .filter(|m| {
+                m.state
+                    .as_ref()
+                    .is_some_and(|s| is_considered_for_downing(s.node_status))
+            })
+            .map(|m| m.id)
+            .filter(|id| !self.unreachable_since.contains_key(id))
+            .collect();
+        let partition = Partition {
+            this_node_id: self.this_node_id,
+            reachable,
+            unreachable: self
+                .unreachable_since
+                .iter()
+                .map(|(id, since)| (*id, now.duration_since(*since)))
+                .collect(),
+            members: &self.cluster_view.known_members,
+        };
+        let decision = strategy.decide(&partition);
+
+        for node_id in &decision {
+            if *node_id == self.this_node_id {
+                log::warn!(
+                    "[Node id: {}] The downing strategy has decided to down the running node",
+                    self.this_node_id
+                );
+            } else {
+                log::info!(
+                    "[Node id: {}] Leader is downing unreachable member {}",
+                    self.this_node_id,
+                    node_id
+                );
+            }
+            self.cluster_view
+                .update_member_status(self.this_node_id, *node_id, NodeStatus::Down);
+        }
+
+        !decision.is_empty()
+    }
+
+    /// Downs a member on behalf of an operator, see [Council::down](crate::Council::down).
+    /// Returns false if the member is unknown, or has already been downed or removed.
+    pub(crate) fn down(&mut self, node_id: NodeId) -> bool {
+        let downable = self
+            .cluster_view
+            .known_members
+            .get(&node_id)
+            .and_then(|m| m.state.as_ref())
+            .is_some_and(|s| s.node_status < NodeStatus::Down);
+        if !downable {
+            return false;
+        }
+        log::warn!(
+            "[Node id: {}] Downing member {} on request",
+            self.this_node_id,
+            node_id
+        );
+        self.cluster_view
+            .update_member_status(self.this_node_id, node_id, NodeStatus::Down)
+    }
+
+    /// Downs the previous incarnations of the members that have restarted, i.e. the members sharing their URL,
+    /// and thus their [unique id](NodeId::unique_id), with a member of a newer generation.
+    /// Only the leader does so, once it has moved the newer incarnation to [NodeStatus::Up] and can reach it, so that
+    /// a node merely claiming the URL of a member can't get it downed.
+    /// Previous incarnations are then removed like any downed member. Returns true if any member has been downed.
+    pub(crate) fn down_restarted_members(&mut self, now: Instant) -> bool {
+        if !self.is_leader() {
+            return false;
+        }
+        let mut latest_incarnations: HashMap<u64, (u64, u64)> = HashMap::new();
+        for member in self.cluster_view.known_members.values() {
+            let is_up = member
+                .state
+                .as_ref()
+                .is_some_and(|s| s.node_status == NodeStatus::Up);
+            if is_up && self.is_reachable(member.id, now) {
+                let incarnation = latest_incarnations.entry(member.id.unique_id).or_default();
+                *incarnation = std::cmp::max(*incarnation, member.id.incarnation());
+            }
+        }
+        let previous_incarnations: Vec<NodeId> = self
+            .cluster_view
+            .known_members
+            .values()
+            .filter(|m| {
+                latest_incarnations
+                    .get(&m.id.unique_id)
+                    .is_some_and(|incarnation| *incarnation > m.id.incarnation())
+                    && m.state
+                        .as_ref()
+                        .is_some_and(|s| s.node_status < NodeStatus::Down)
+            })
+            .map(|m| m.id)
+            .collect();
+        for node_id in &previous_incarnations {
+            log::info!(
+                "[Node id: {}] Member {} has restarted, downing its previous incarnation",
+                self.this_node_id,
+                node_id
+            );
+            self.cluster_view
+                .update_member_status(self.this_node_id, *node_id, NodeStatus::Down);
+        }
+        !previous_incarnations.is_empty()
+    }
+
+    /// Tells whether a newer incarnation of a member is known, i.e. a member with the same URL and a newer generation
+    pub(crate) fn has_restarted(&self, node_id: NodeId) -> bool {
+        self.cluster_view.known_members.keys().any(|other| {
+            other.unique_id == node_id.unique_id && other.incarnation() > node_id.incarnation()
+        })
+    }
+
+    /// Tells whether a member has been downed, and will never be admitted back into the cluster
+    pub fn is_quarantined(&self, node_id: NodeId) -> bool {
+        self.cluster_view.quarantine.contains(&node_id)
+    }
+}
+
+fn is_considered_for_downing(node_status: NodeStatus) -> bool {
+    !matches!(
+        node_status,
+        NodeStatus::Exiting | NodeStatus::Down | NodeStatus::Removed
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        time::Duration,
+    };
+
+    use super::*;
+
+    fn node(unique_id: u64, generation: u64) -> NodeId {
+        NodeId {
+            unique_id,
+            generation,
+        }
+    }
+
+    fn partition<'a>(
+        members: &'a HashMap<NodeId, MemberView>,
+        reachable: &[NodeId],
+        unreachable: &[NodeId],
+    ) -> Partition<'a> {
+        Partition {
+            this_node_id: reachable[0],
+            reachable: reachable.iter().cloned().collect(),
+            unreachable: unreachable
+                .iter()
+                .map(|id| (*id, Duration::from_secs(10)))
+                .collect(),
+            members,
+        }
+    }
+
+    #[test]
+    fn auto_down_after_timeout() {
+        let members = HashMap::new();
+        let mut partition = partition(&members, &[node(1, 1)], &[node(2, 1), node(3, 1)]);
+        partition
+            .unreachable
+            .insert(node(3, 1), Duration::from_secs(1));
+
+        let strategy = AutoDownAfterTimeout {
+            timeout: Duration::from_secs(5),
+        };
+        assert_eq!(strategy.decide(&partition), HashSet::from([node(2, 1)]));
+    }
+
+    #[test]
+    fn keep_majority() {
+        let members = HashMap::new();
+        let (a, b, c) = (node(1, 1), node(2, 1), node(3, 1));
+
+        let majority = partition(&members, &[a, b], &[c]);
+        assert_eq!(KeepMajority.decide(&majority), HashSet::from([c]));
+
+        let minority = partition(&members, &[c], &[a, b]);
+        assert_eq!(KeepMajority.decide(&minority), HashSet::from([c]));
+
+        // On equal sides, the side containing the lowest node id is kept
+        let with_lowest = partition(&members, &[a], &[b]);
+        assert_eq!(KeepMajority.decide(&with_lowest), HashSet::from([b]));
+        let without_lowest = partition(&members, &[b], &[a]);
+        assert_eq!(KeepMajority.decide(&without_lowest), HashSet::from([b]));
+    }
+
+    #[test]
+    fn static_quorum() {
+        let members = HashMap::new();
+        let (a, b, c) = (node(1, 1), node(2, 1), node(3, 1));
+        let strategy = StaticQuorum { quorum_size: 2 };
+
+        assert_eq!(
+            strategy.decide(&partition(&members, &[a, b], &[c])),
+            HashSet::from([c])
+        );
+        assert_eq!(
+            strategy.decide(&partition(&members, &[c], &[a, b])),
+            HashSet::from([c])
+        );
+    }
+
+    #[test]
+    fn keep_oldest() {
+        let members = HashMap::new();
+        let (oldest, b, c) = (node(3, 1), node(1, 2), node(2, 3));
+
+        let strategy = KeepOldest {
+            down_if_alone: false,
+        };
+        assert_eq!(
+            strategy.decide(&partition(&members, &[oldest], &[b, c])),
+            HashSet::from([b, c])
+        );
+        assert_eq!(
+            strategy.decide(&partition(&members, &[b, c], &[oldest])),
+            HashSet::from([b, c])
+        );
+
+        let strategy = KeepOldest {
+            down_if_alone: true,
+        };
+        assert_eq!(
+            strategy.decide(&partition(&members, &[oldest], &[b, c])),
+            HashSet::from([oldest])
+        );
+        assert_eq!(
+            strategy.decide(&partition(&members, &[b, c], &[oldest])),
+            HashSet::from([oldest])
+        );
+        assert_eq!(
+            strategy.decide(&partition(&members, &[oldest, b], &[c])),
+            HashSet::from([c])
+        );
+    }
+
+    #[test]
+    fn keep_oldest_compares_generations_in_seconds_and_nanoseconds() {
+        let members = HashMap::new();
+        // Started by an upgraded build, which counts generations in nanoseconds, a second before a member started by
+        // an older build, which counts them in seconds
+        let oldest = node(2, 1_700_000_000_000_000_000);
+        let older_build = node(1, 1_700_000_001);
+
+        let strategy = KeepOldest {
+            down_if_alone: false,
+        };
+        assert_eq!(
+            strategy.decide(&partition(&members, &[oldest], &[older_build])),
+            HashSet::from([older_build])
+        );
+        assert_eq!(
+            strategy.decide(&partition(&members, &[older_build], &[oldest])),
+            HashSet::from([older_build])
+        );
+    }
+}
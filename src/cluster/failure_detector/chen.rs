@@ -0,0 +1,168 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use super::FailureDetector;
+use crate::node::NodeId;
+
+/// Configures the [ChenFailureDetector]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChenSettings {
+    /// How many arrival times are kept per member to estimate the next one. Defaults to 100.
+    pub window_size: usize,
+    /// How long after the expected arrival of the next heartbeat a member is suspected. Defaults to 3 seconds.
+    pub safety_margin: Duration,
+    /// The expected interval between heartbeats until actual intervals have been measured. Defaults to 1 second.
+    pub first_heartbeat_estimate: Duration,
+}
+
+impl Default for ChenSettings {
+    fn default() -> Self {
+        Self {
+            window_size: 100,
+            safety_margin: Duration::from_secs(3),
+            first_heartbeat_estimate: Duration::from_secs(1),
+        }
+    }
+}
+
+/// A [FailureDetector] implementing the adaptive estimator of Chen, Toueg & Aguilera (2002),
+/// "On the Quality of Service of Failure Detectors".
+///
+/// The arrival times of the last heartbeats are used to estimate when the next one is expected, and a member is
+/// suspected once a constant safety margin has elapsed past that estimate. Compared to [DeadlineFailureDetector](super::DeadlineFailureDetector),
+/// the estimate follows the actual pace of heartbeats; compared to [PhiAccrualFailureDetector](super::PhiAccrualFailureDetector),
+/// the safety margin doesn't grow with the variability of the network.
+#[derive(Clone, Debug)]
+pub struct ChenFailureDetector {
+    pub settings: ChenSettings,
+    members: HashMap<NodeId, ChenMember>,
+}
+
+#[derive(Clone, Debug)]
+struct ChenMember {
+    last_heartbeat: u64,
+    arrivals: VecDeque<Instant>,
+}
+
+impl ChenFailureDetector {
+    pub fn new(settings: ChenSettings) -> Self {
+        Self {
+            settings,
+            members: HashMap::new(),
+        }
+    }
+
+    /// Returns when the next heartbeat of a member is expected, or `None` if no heartbeat was ever received from it
+    pub fn expected_arrival(&self, node_id: NodeId) -> Option<Instant> {
+        let arrivals = &self.members.get(&node_id)?.arrivals;
+        let first = *arrivals.front()?;
+        let last = *arrivals.back()?;
+        let n = arrivals.len();
+        if n < 2 {
+            return Some(last + self.settings.first_heartbeat_estimate);
+        }
+
+        // The i-th arrival is expected at i * interval after the first one, shifted by the average delay of
+        // the observed arrivals compared to that schedule
+        let interval = last.saturating_duration_since(first).as_secs_f64() / (n - 1) as f64;
+        let average_delay = arrivals
+            .iter()
+            .enumerate()
+            .map(|(i, arrival)| {
+                arrival.saturating_duration_since(first).as_secs_f64() - interval * i as f64
+            })
+            .sum::<f64>()
+            / n as f64;
+        Some(first + Duration::from_secs_f64((average_delay + interval * n as f64).max(0.0)))
+    }
+}
+
+impl FailureDetector for ChenFailureDetector {
+    fn record_heartbeat(&mut self, node_id: NodeId, heartbeat: u64, now: Instant) {
+        let member = self.members.entry(node_id).or_insert_with(|| ChenMember {
+            last_heartbeat: heartbeat,
+            arrivals: VecDeque::new(),
+        });
+        if !member.arrivals.is_empty() && member.last_heartbeat >= heartbeat {
+            return;
+        }
+        while member.arrivals.len() >= self.settings.window_size.max(1) {
+            member.arrivals.pop_front();
+        }
+        member.last_heartbeat = heartbeat;
+        member.arrivals.push_back(now);
+    }
+
+    fn is_live(&self, node_id: NodeId, now: Instant) -> bool {
+        self.expected_arrival(node_id)
+            .is_some_and(|expected_arrival| now <= expected_arrival + self.settings.safety_margin)
+    }
+
+    fn remove(&mut self, node_id: NodeId) {
+        self.members.remove(&node_id);
+    }
+
+    fn monitored_members(&self) -> HashMap<NodeId, u64> {
+        self.members
+            .iter()
+            .map(|(id, m)| (*id, m.last_heartbeat))
+            .collect()
+    }
+
+    /// How late the next heartbeat is compared to its expected arrival, in seconds
+    fn suspicion_level(&self, node_id: NodeId, now: Instant) -> Option<f64> {
+        self.expected_arrival(node_id).map(|expected_arrival| {
+            if now >= expected_arrival {
+                now.duration_since(expected_arrival).as_secs_f64()
+            } else {
+                -expected_arrival.duration_since(now).as_secs_f64()
+            }
+        })
+    }
+
+    fn box_clone(&self) -> Box<dyn FailureDetector> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_arrival_follows_the_pace_of_heartbeats() {
+        let mut failure_detector = ChenFailureDetector::new(ChenSettings {
+            safety_margin: Duration::from_millis(500),
+            ..Default::default()
+        });
+        let node_id = NodeId {
+            unique_id: 1,
+            generation: 1,
+        };
+        let start = Instant::now();
+        assert_eq!(failure_detector.expected_arrival(node_id), None);
+        assert!(!failure_detector.is_live(node_id, start));
+
+        failure_detector.record_heartbeat(node_id, 1, start);
+        assert_eq!(
+            failure_detector.expected_arrival(node_id),
+            Some(start + Duration::from_secs(1))
+        );
+
+        for (heartbeat, seconds) in [(3, 2), (5, 4), (7, 6)] {
+            failure_detector.record_heartbeat(
+                node_id,
+                heartbeat,
+                start + Duration::from_secs(seconds),
+            );
+        }
+        let expected_arrival = failure_detector.expected_arrival(node_id).unwrap();
+        let delta = expected_arrival.duration_since(start + Duration::from_secs(8));
+        assert!(delta < Duration::from_millis(1), "{:?}", delta);
+
+        assert!(failure_detector.is_live(node_id, start + Duration::from_millis(8400)));
+        assert!(!failure_detector.is_live(node_id, start + Duration::from_millis(8600)));
+    }
+}
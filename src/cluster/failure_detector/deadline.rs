@@ -0,0 +1,108 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use super::FailureDetector;
+use crate::node::NodeId;
+
+/// A [FailureDetector] that considers a member unreachable as soon as no heartbeat has been received from it for
+/// longer than the expected heartbeat interval plus an acceptable pause.
+///
+/// Its behavior is easy to predict, but it doesn't adapt to network conditions: the deadline must be generous enough
+/// to tolerate the worst delays of the network, which in turn delays the detection of actual failures.
+#[derive(Clone, Debug)]
+pub struct DeadlineFailureDetector {
+    pub heartbeat_interval: Duration,
+    pub acceptable_heartbeat_pause: Duration,
+    members: HashMap<NodeId, (u64, Instant)>,
+}
+
+impl DeadlineFailureDetector {
+    pub fn new(heartbeat_interval: Duration, acceptable_heartbeat_pause: Duration) -> Self {
+        Self {
+            heartbeat_interval,
+            acceptable_heartbeat_pause,
+            members: HashMap::new(),
+        }
+    }
+
+    fn deadline(&self) -> Duration {
+        self.heartbeat_interval + self.acceptable_heartbeat_pause
+    }
+}
+
+impl FailureDetector for DeadlineFailureDetector {
+    fn record_heartbeat(&mut self, node_id: NodeId, heartbeat: u64, now: Instant) {
+        match self.members.get_mut(&node_id) {
+            Some((last_heartbeat, received_at)) if *last_heartbeat < heartbeat => {
+                *last_heartbeat = heartbeat;
+                *received_at = now;
+            }
+            None => {
+                self.members.insert(node_id, (heartbeat, now));
+            }
+            _ => (),
+        }
+    }
+
+    fn is_live(&self, node_id: NodeId, now: Instant) -> bool {
+        self.members.get(&node_id).is_some_and(|(_, received_at)| {
+            now.saturating_duration_since(*received_at) <= self.deadline()
+        })
+    }
+
+    fn remove(&mut self, node_id: NodeId) {
+        self.members.remove(&node_id);
+    }
+
+    fn monitored_members(&self) -> HashMap<NodeId, u64> {
+        self.members
+            .iter()
+            .map(|(id, (last_heartbeat, _))| (*id, *last_heartbeat))
+            .collect()
+    }
+
+    /// The time elapsed since the last heartbeat, as a fraction of the deadline
+    fn suspicion_level(&self, node_id: NodeId, now: Instant) -> Option<f64> {
+        self.members.get(&node_id).map(|(_, received_at)| {
+            now.saturating_duration_since(*received_at).as_secs_f64()
+                / self.deadline().as_secs_f64()
+        })
+    }
+
+    fn box_clone(&self) -> Box<dyn FailureDetector> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn members_are_unreachable_past_the_deadline() {
+        let mut failure_detector =
+            DeadlineFailureDetector::new(Duration::from_secs(1), Duration::from_secs(2));
+        let node_id = NodeId {
+            unique_id: 1,
+            generation: 1,
+        };
+        let now = Instant::now();
+        assert!(!failure_detector.is_live(node_id, now));
+
+        failure_detector.record_heartbeat(node_id, 1, now);
+        assert!(failure_detector.is_live(node_id, now + Duration::from_secs(3)));
+        assert!(!failure_detector.is_live(node_id, now + Duration::from_secs(4)));
+
+        // Stale heartbeats don't reset the deadline
+        failure_detector.record_heartbeat(node_id, 1, now + Duration::from_secs(4));
+        assert!(!failure_detector.is_live(node_id, now + Duration::from_secs(4)));
+        failure_detector.record_heartbeat(node_id, 2, now + Duration::from_secs(4));
+        assert!(failure_detector.is_live(node_id, now + Duration::from_secs(4)));
+        assert_eq!(
+            failure_detector.unreachable_members(now + Duration::from_secs(8)),
+            vec![node_id]
+        );
+    }
+}
@@ -0,0 +1,385 @@
+use std::{
+    collections::{HashMap, LinkedList},
+    time::{Duration, Instant},
+};
+
+use super::FailureDetector;
+use crate::node::NodeId;
+
+/// Configures the [PhiAccrualFailureDetector]. The defaults are those of Akka's phi-accrual failure detector,
+/// except for the window size.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PhiAccrualSettings {
+    /// A member is considered unreachable once its phi reaches this threshold. Defaults to 8.0.
+    pub phi_threshold: f64,
+    /// How many intervals between heartbeats are kept to compute their mean and standard deviation. Defaults to 100.
+    pub window_size: usize,
+    /// A lower bound for the standard deviation, so that very regular heartbeats don't make phi overly sensitive
+    /// to small delays. Defaults to 100ms.
+    pub min_std_deviation: Duration,
+    /// How long heartbeats may pause on top of the expected interval (garbage collection, network hiccups ...)
+    /// before phi starts growing. Defaults to 3 seconds.
+    pub acceptable_heartbeat_pause: Duration,
+    /// The expected interval between heartbeats until actual intervals have been measured. Defaults to 1 second.
+    pub first_heartbeat_estimate: Duration,
+}
+
+impl Default for PhiAccrualSettings {
+    fn default() -> Self {
+        Self {
+            phi_threshold: 8.0,
+            window_size: 100,
+            min_std_deviation: Duration::from_millis(100),
+            acceptable_heartbeat_pause: Duration::from_secs(3),
+            first_heartbeat_estimate: Duration::from_secs(1),
+        }
+    }
+}
+
+/// The [PhiAccrualFailureDetector] estimates the probability of a node being unreachable.
+///
+/// Every time a node gossips with the running node, its last heartbeat value is recorded, and
+/// the last intervals between each hearteat are recorded in a bounded window.
+/// This in turn lets us implement a Phi-accrual failure detector (Hayashibara, N., Défago, X., Yared, R., & Katayama, T. (2004)),
+/// a failure detector popularized by Akka on the JVM.
+///
+/// To quote Akka's documentation:
+/// > The suspicion level of failure is represented by a value called phi.
+/// > The basic idea of the phi failure detector is to express the value of phi on a scale that is dynamically adjusted to reflect current network conditions.
+///
+/// Intervals between heartbeats are assumed to follow a normal distribution, and phi is `-log10(1 - F(t))`, where `F` is the
+/// cumulative distribution function of that distribution and `t` the time elapsed since the last heartbeat.
+/// A phi of 1 means a 10% chance of mistakenly suspecting the member, a phi of 2 a 1% chance, and so on.
+#[derive(Clone, Debug)]
+pub struct PhiAccrualFailureDetector {
+    pub(crate) members: HashMap<NodeId, PhiAccrualMember>,
+    pub settings: PhiAccrualSettings,
+}
+
+impl PhiAccrualFailureDetector {
+    pub fn new(settings: PhiAccrualSettings) -> Self {
+        Self {
+            members: HashMap::new(),
+            settings,
+        }
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = (NodeId, &PhiAccrualMember)> {
+        self.members.iter().map(|(id, m)| (*id, m))
+    }
+
+    /// Returns the current suspicion level of a member, or `None` if no heartbeat was ever received from it
+    pub fn phi(&self, node_id: NodeId, now: Instant) -> Option<f64> {
+        self.members
+            .get(&node_id)
+            .map(|n| n.phi(now, &self.settings))
+    }
+}
+
+impl FailureDetector for PhiAccrualFailureDetector {
+    fn record_heartbeat(&mut self, node_id: NodeId, heartbeat: u64, now: Instant) {
+        match self.members.get_mut(&node_id) {
+            Some(member) if member.last_heartbeat < heartbeat => {
+                member.record_heartbeat(heartbeat, now, &self.settings);
+            }
+            None => {
+                self.members.insert(
+                    node_id,
+                    PhiAccrualMember::new(heartbeat, now, &self.settings),
+                );
+            }
+            _ => (),
+        }
+    }
+
+    fn is_live(&self, node_id: NodeId, now: Instant) -> bool {
+        self.phi(node_id, now)
+            .is_some_and(|phi| phi < self.settings.phi_threshold)
+    }
+
+    fn remove(&mut self, node_id: NodeId) {
+        self.members.remove(&node_id);
+    }
+
+    fn monitored_members(&self) -> HashMap<NodeId, u64> {
+        self.members
+            .iter()
+            .map(|(id, m)| (*id, m.last_heartbeat))
+            .collect()
+    }
+
+    fn suspicion_level(&self, node_id: NodeId, now: Instant) -> Option<f64> {
+        self.phi(node_id, now)
+    }
+
+    fn box_clone(&self) -> Box<dyn FailureDetector> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PhiAccrualMember {
+    pub last_heartbeat: u64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub last_heartbeat_received_at: Instant,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    heartbeats_intervals: LinkedList<Duration>,
+    pub heartbeats_intervals_mean: Option<Duration>,
+    pub hearbeats_interval_std_dev: Option<Duration>,
+    pub heartbeats_min_interval: Option<Duration>,
+    pub heartbeats_max_interval: Option<Duration>,
+}
+
+impl PhiAccrualMember {
+    /// Creates a member whose window is seeded with two intervals around the first heartbeat estimate,
+    /// so that phi is meaningful right after the first heartbeat, like in Akka.
+    fn new(last_heartbeat: u64, now: Instant, settings: &PhiAccrualSettings) -> Self {
+        let mut member = Self {
+            last_heartbeat,
+            last_heartbeat_received_at: now,
+            heartbeats_intervals: LinkedList::new(),
+            hearbeats_interval_std_dev: None,
+            heartbeats_intervals_mean: None,
+            heartbeats_min_interval: None,
+            heartbeats_max_interval: None,
+        };
+        let estimate = settings.first_heartbeat_estimate;
+        member.insert_interval(estimate - estimate / 4, settings);
+        member.insert_interval(estimate + estimate / 4, settings);
+        member.refresh_stats();
+        member
+    }
+
+    fn refresh_stats(&mut self) {
+        let count = self.heartbeats_intervals.len();
+        if count > 0 {
+            let sum: Duration = self.heartbeats_intervals.iter().sum();
+            let mean_heartbeat_duration = sum / count as u32;
+            let mean_heartbeat_duration_f64 = mean_heartbeat_duration.as_secs_f64();
+            let variance_f64: f64 = self
+                .heartbeats_intervals
+                .iter()
+                .map(|interval| {
+                    let diff = mean_heartbeat_duration_f64 - interval.as_secs_f64();
+                    diff * diff
+                })
+                .sum::<f64>()
+                / count as f64;
+            let std_dev = Duration::from_secs_f64(variance_f64.sqrt());
+
+            self.heartbeats_intervals_mean = Some(mean_heartbeat_duration);
+            self.hearbeats_interval_std_dev = Some(std_dev);
+        } else {
+            self.heartbeats_intervals_mean = None;
+            self.hearbeats_interval_std_dev = None;
+        }
+    }
+
+    /// Computes phi, using the logistic approximation of the normal cumulative distribution function Akka uses.
+    /// The acceptable heartbeat pause is added to the mean interval, and the standard deviation is bounded by
+    /// the minimum standard deviation.
+    fn phi(&self, now: Instant, settings: &PhiAccrualSettings) -> f64 {
+        let elapsed = now
+            .saturating_duration_since(self.last_heartbeat_received_at)
+            .as_secs_f64();
+        let mean = self
+            .heartbeats_intervals_mean
+            .unwrap_or(settings.first_heartbeat_estimate)
+            + settings.acceptable_heartbeat_pause;
+        let std_dev = self
+            .hearbeats_interval_std_dev
+            .unwrap_or_default()
+            .max(settings.min_std_deviation);
+        phi(elapsed, mean.as_secs_f64(), std_dev.as_secs_f64())
+    }
+
+    fn insert_interval(&mut self, interval: Duration, settings: &PhiAccrualSettings) {
+        while self.heartbeats_intervals.len() >= settings.window_size.max(1) {
+            self.heartbeats_intervals.pop_front();
+        }
+        if self.heartbeats_min_interval.is_none() {
+            self.heartbeats_min_interval = Some(interval)
+        } else {
+            self.heartbeats_min_interval =
+                std::cmp::min(self.heartbeats_min_interval, Some(interval));
+        }
+        self.heartbeats_max_interval = std::cmp::max(self.heartbeats_max_interval, Some(interval));
+        self.heartbeats_intervals.push_back(interval);
+    }
+
+    fn record_heartbeat(
+        &mut self,
+        last_heartbeat: u64,
+        now: Instant,
+        settings: &PhiAccrualSettings,
+    ) {
+        let received_heartbeats_since_last_record =
+            last_heartbeat.saturating_sub(self.last_heartbeat);
+        if received_heartbeats_since_last_record == 0 {
+            return;
+        }
+        let new_last_heartbeat_receveived_at = now;
+        let elapsed_time = new_last_heartbeat_receveived_at
+            .saturating_duration_since(self.last_heartbeat_received_at);
+        let mean_heartbeat_time = Duration::from_nanos(
+            u64::try_from(
+                elapsed_time.as_nanos() / u128::from(received_heartbeats_since_last_record),
+            )
+            .unwrap_or(u64::MAX),
+        );
+        // Only the last intervals fit in the window anyway
+        let window_size = u64::try_from(settings.window_size.max(1)).unwrap_or(u64::MAX);
+        for _ in 0..received_heartbeats_since_last_record.min(window_size) {
+            self.insert_interval(mean_heartbeat_time, settings);
+        }
+        self.last_heartbeat = last_heartbeat;
+        self.last_heartbeat_received_at = new_last_heartbeat_receveived_at;
+        self.refresh_stats();
+    }
+}
+
+/// Computes `-log10(1 - F(elapsed))`, `F` being the cumulative distribution function of the normal distribution
+/// with the given mean and standard deviation, approximated with a logistic function.
+fn phi(elapsed: f64, mean: f64, std_dev: f64) -> f64 {
+    let y = (elapsed - mean) / std_dev;
+    let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+    if elapsed > mean {
+        -(e / (1.0 + e)).log10()
+    } else {
+        -(1.0 - 1.0 / (1.0 + e)).log10()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::sleep;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn failure_detector_member_stats() {
+        let expected_mean_heartbeat_time = Duration::from_millis(5);
+        let settings = PhiAccrualSettings {
+            first_heartbeat_estimate: expected_mean_heartbeat_time,
+            ..Default::default()
+        };
+        let mut member = PhiAccrualMember::new(1, Instant::now(), &settings);
+
+        sleep(expected_mean_heartbeat_time).await;
+        member.record_heartbeat(2, Instant::now(), &settings);
+        sleep(expected_mean_heartbeat_time * 3).await;
+        member.record_heartbeat(5, Instant::now(), &settings);
+        sleep(expected_mean_heartbeat_time * 10).await;
+        member.record_heartbeat(15, Instant::now(), &settings);
+        sleep(expected_mean_heartbeat_time * 5).await;
+        member.record_heartbeat(20, Instant::now(), &settings);
+
+        if let Some(mean) = member.heartbeats_intervals_mean {
+            let min = expected_mean_heartbeat_time.mul_f64(0.9);
+            let max = expected_mean_heartbeat_time.mul_f64(1.2);
+            println!("Mean heartbeat duration is {:?}", mean);
+            assert!(
+                mean >= min,
+                "Expected mean to be >= {:?} but mean was {:?}",
+                min,
+                mean
+            );
+            assert!(
+                mean <= max,
+                "Expected mean to be <= {:?} but mean was {:?}",
+                max,
+                mean
+            );
+        } else {
+            panic!();
+        }
+
+        if let Some(std_dev) = member.hearbeats_interval_std_dev {
+            println!("Heartbeat Std dev. is {:?}", std_dev);
+            assert!(
+                std_dev <= Duration::from_millis(1),
+                "Expected std dev to be <= 1ms but got {:?}",
+                std_dev
+            );
+        }
+    }
+
+    #[test]
+    fn heartbeat_jumps_fill_the_window_at_most() {
+        let settings = PhiAccrualSettings {
+            window_size: 10,
+            ..Default::default()
+        };
+        let started_at = Instant::now();
+        let mut member = PhiAccrualMember::new(1, started_at, &settings);
+
+        // A jump of 2^32 heartbeats fills the window with sub-microsecond intervals instead of being truncated
+        member.record_heartbeat(
+            1 + (1 << 32),
+            started_at + Duration::from_secs(1),
+            &settings,
+        );
+        assert_eq!(member.heartbeats_intervals.len(), 10);
+        assert!(member
+            .heartbeats_intervals
+            .iter()
+            .all(|i| *i < Duration::from_micros(1)));
+
+        // Stale or repeated heartbeats are ignored
+        member.record_heartbeat(1 << 32, started_at + Duration::from_secs(2), &settings);
+        assert_eq!(
+            member.last_heartbeat_received_at,
+            started_at + Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn phi_grows_with_the_time_elapsed_since_the_last_heartbeat() {
+        let (mean, std_dev) = (1.0, 0.2);
+        assert!((phi(mean, mean, std_dev) - 0.5f64.log10().abs()).abs() < 0.01);
+
+        let phis: Vec<f64> = [0.0, 0.5, 1.0, 1.5, 2.0, 3.0, 10.0]
+            .into_iter()
+            .map(|elapsed| phi(elapsed, mean, std_dev))
+            .collect();
+        assert!(phis.iter().all(|phi| !phi.is_nan() && *phi >= 0.0));
+        assert!(phis.windows(2).all(|w| w[0] <= w[1]), "{:?}", phis);
+
+        // Three standard deviations above the mean, the probability of a mistake is about 0.1%
+        assert!((phi(mean + 3.0 * std_dev, mean, std_dev) - 3.0).abs() < 0.2);
+    }
+
+    #[test]
+    fn members_become_unreachable_after_the_acceptable_pause() {
+        let settings = PhiAccrualSettings {
+            acceptable_heartbeat_pause: Duration::from_secs(2),
+            ..Default::default()
+        };
+        let node_id = NodeId {
+            unique_id: 1,
+            generation: 1,
+        };
+        let mut failure_detector = PhiAccrualFailureDetector::new(settings);
+        let last_heartbeat_received_at = Instant::now();
+        assert_eq!(
+            failure_detector.phi(node_id, last_heartbeat_received_at),
+            None
+        );
+        assert!(!failure_detector.is_live(node_id, last_heartbeat_received_at));
+
+        failure_detector.record_heartbeat(node_id, 1, last_heartbeat_received_at);
+        assert!(
+            failure_detector.is_live(node_id, last_heartbeat_received_at + Duration::from_secs(1))
+        );
+        assert!(
+            failure_detector.is_live(node_id, last_heartbeat_received_at + Duration::from_secs(3))
+        );
+        assert!(
+            !failure_detector.is_live(node_id, last_heartbeat_received_at + Duration::from_secs(5))
+        );
+    }
+}
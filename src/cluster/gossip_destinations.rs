@@ -1,24 +1,26 @@
 use rand::seq::SliceRandom;
 use url::Url;
 
-use crate::cluster::{views::PartialClusterView, Cluster};
+use crate::cluster::{digest::ClusterDigest, views::PartialClusterView, Cluster};
 
 const GOSSIP_DESTINATIONS_SAMPLE_SIZE: usize = 3;
 
 #[derive(Debug)]
 pub(crate) struct GossipDestination {
     pub(crate) destination_url: Url,
-    pub(crate) cluster_view: PartialClusterView,
+    /// Set unless the destination is known not to support the `digest-gossip` [feature](crate::protocol)
+    pub(crate) digest: Option<ClusterDigest>,
+    /// The full view of the running node, set unless the destination is known to support digest gossip.
+    /// Unknown peers are sent their digest first, and the full view if they turn out not to support digests.
+    pub(crate) full_view: Option<PartialClusterView>,
 }
 
 impl Cluster {
     /// Selects destinations to gossip with, up to a maximum of three
     pub(crate) fn select_gossip_destinations(&mut self) -> Vec<GossipDestination> {
         let mut destinations = Vec::new();
-        let cluster_view = PartialClusterView {
-            this_node_id: self.this_node_id,
-            members: self.cluster_view.known_members.clone(),
-        };
+        let digest = self.digest();
+        let mut full_view = None;
 
         for url in &self.unknwon_peer_nodes {
             if destinations.len() >= GOSSIP_DESTINATIONS_SAMPLE_SIZE
@@ -29,26 +31,27 @@ impl Cluster {
 
             {
                 log::debug!(
-                    "[Node Id: {}] Exchanging cluster views with unknown peer node {}",
+                    "[Node Id: {}] Exchanging digests with unknown peer node {}",
                     self.this_node_id,
                     url
                 );
                 destinations.push(GossipDestination {
                     destination_url: url.clone(),
-                    cluster_view: cluster_view.clone(),
+                    digest: Some(digest.clone()),
+                    full_view: Some(full_view.get_or_insert_with(|| self.full_view()).clone()),
                 });
             }
         }
 
-        let urls: Vec<&Url> = self
+        let members: Vec<(&Url, bool)> = self
             .cluster_view
             .known_members
-            .iter()
-            .filter_map(|(_, m)| {
+            .values()
+            .filter_map(|m| {
                 if m.advertised_addr == self.this_advertised_url {
                     None
                 } else {
-                    Some(&m.advertised_addr)
+                    Some((&m.advertised_addr, m.protocol.supports("digest-gossip")))
                 }
             })
             .collect();
@@ -60,14 +63,25 @@ impl Cluster {
 
         if remaining_exchanges > 0 {
             log::debug!(
-                "[Node Id: {}] Exchanging heartbeats with {} random members",
+                "[Node Id: {}] Exchanging digests with {} random members",
                 self.this_node_id,
                 remaining_exchanges
             );
-            for url in urls.choose_multiple(&mut rand::thread_rng(), remaining_exchanges) {
-                destinations.push(GossipDestination {
-                    destination_url: (*url).clone(),
-                    cluster_view: cluster_view.clone(),
+            for (url, gossips_digests) in
+                members.choose_multiple(&mut rand::thread_rng(), remaining_exchanges)
+            {
+                destinations.push(if *gossips_digests {
+                    GossipDestination {
+                        destination_url: (*url).clone(),
+                        digest: Some(digest.clone()),
+                        full_view: None,
+                    }
+                } else {
+                    GossipDestination {
+                        destination_url: (*url).clone(),
+                        digest: None,
+                        full_view: Some(full_view.get_or_insert_with(|| self.full_view()).clone()),
+                    }
                 })
             }
         }
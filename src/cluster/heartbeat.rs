@@ -0,0 +1,68 @@
+use std::time::Instant;
+
+use url::Url;
+
+use crate::{
+    cluster::Cluster,
+    node::{NodeId, NodeStatus},
+};
+
+impl Cluster {
+    /// Returns the members the running node sends heartbeats to.
+    ///
+    /// Members are arranged on a ring ordered by [NodeId], and every member heartbeats the members that follow it
+    /// on the ring, so that every member is monitored by the same number of members, like in Akka.
+    /// Down and removed members are left out of the ring, and members that don't support the `heartbeats`
+    /// [feature](crate::protocol) aren't sent any heartbeat: they only learn about heartbeats through gossip.
+    pub(crate) fn heartbeat_receivers(&self) -> Vec<Url> {
+        let mut ring: Vec<NodeId> = self
+            .cluster_view
+            .known_members
+            .values()
+            .filter(|m| {
+                m.id == self.this_node_id
+                    || m.state.as_ref().is_some_and(|s| {
+                        !matches!(s.node_status, NodeStatus::Down | NodeStatus::Removed)
+                    })
+            })
+            .map(|m| m.id)
+            .collect();
+        ring.sort();
+
+        let Some(position) = ring.iter().position(|id| *id == self.this_node_id) else {
+            return Vec::new();
+        };
+        ring.iter()
+            .cycle()
+            .skip(position + 1)
+            .take(std::cmp::min(
+                self.monitored_by_nr_of_members,
+                ring.len() - 1,
+            ))
+            .filter_map(|id| self.cluster_view.known_members.get(id))
+            .filter(|m| m.protocol.supports("heartbeats"))
+            .map(|m| m.advertised_addr.clone())
+            .collect()
+    }
+
+    /// Records the heartbeat of a member, received either through a heartbeat or a gossip exchange
+    pub(crate) fn record_heartbeat(&mut self, node_id: NodeId, heartbeat: u64, now: Instant) {
+        if node_id == self.this_node_id {
+            return;
+        }
+        let Some(state) = self
+            .cluster_view
+            .known_members
+            .get_mut(&node_id)
+            .and_then(|m| m.state.as_mut())
+        else {
+            return;
+        };
+        if heartbeat > state.heartbeat {
+            state.heartbeat = heartbeat;
+            self.cluster_view.heartbeats.insert(node_id, heartbeat);
+        }
+        self.failure_detector
+            .record_heartbeat(node_id, heartbeat, now);
+    }
+}
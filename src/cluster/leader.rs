@@ -0,0 +1,71 @@
+use std::time::SystemTime;
+
+use crate::{
+    cluster::Cluster,
+    node::{NodeId, NodeStatus},
+};
+
+impl Cluster {
+    /// Performs the duties of the cluster leader, provided the running node is the leader
+    /// and the cluster has converged:
+    /// - [NodeStatus::Joining] members are moved to [NodeStatus::Up], unless the running node is
+    ///   [bootstrapping](super::bootstrap) and no cluster has been formed yet. Members the
+    ///   [AdmissionHook](super::admission::AdmissionHook) denies are moved to [NodeStatus::Down] instead
+    /// - [NodeStatus::Leaving] members are moved to [NodeStatus::Exiting]
+    /// - [NodeStatus::Exiting] and [NodeStatus::Down] members are moved to [NodeStatus::Removed]
+    ///
+    /// Every transition bumps the version of the member, so that it wins over older views
+    /// when it is gossiped to the rest of the cluster.
+    /// Returns true if at least one member has changed.
+    pub(crate) fn perform_leader_actions(&mut self) -> bool {
+        if !self.is_leader() || !self.has_converged() {
+            return false;
+        }
+
+        let transitions: Vec<(NodeId, NodeStatus)> = self
+            .cluster_view
+            .known_members
+            .values()
+            .filter_map(|member| {
+                let next_status = match member.state.as_ref()?.node_status {
+                    NodeStatus::Joining => NodeStatus::Up,
+                    NodeStatus::Leaving => NodeStatus::Exiting,
+                    NodeStatus::Exiting | NodeStatus::Down => NodeStatus::Removed,
+                    _ => return None,
+                };
+                Some((member.id, next_status))
+            })
+            .collect();
+        let transitions: Vec<(NodeId, NodeStatus)> = if self.can_move_joining_members_up() {
+            transitions
+        } else {
+            transitions
+                .into_iter()
+                .filter(|(_, status)| *status != NodeStatus::Up)
+                .collect()
+        };
+        let transitions: Vec<(NodeId, NodeStatus)> = transitions
+            .into_iter()
+            .map(|(node_id, status)| match status {
+                NodeStatus::Up => (
+                    node_id,
+                    self.status_after_admission(node_id, SystemTime::now()),
+                ),
+                _ => (node_id, status),
+            })
+            .collect();
+
+        for (node_id, status) in &transitions {
+            log::info!(
+                "[Node id: {}] Leader is moving member {} to {}",
+                self.this_node_id,
+                node_id,
+                status
+            );
+            self.cluster_view
+                .update_member_status(self.this_node_id, *node_id, *status);
+        }
+
+        !transitions.is_empty()
+    }
+}
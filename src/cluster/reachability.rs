@@ -0,0 +1,302 @@
+//! Which members consider which other members unreachable.
+//!
+//! The [failure detector](super::failure_detector) of each node only tells whether the running node can reach a member.
+//! Every node publishes those observations as records of the [Reachability] table, which is gossiped along with
+//! the member views, so that any node can tell whether a member is unreachable from a single observer
+//! or from the whole cluster.
+//!
+//! Only the observer updates its own records, and every update gets a version greater than any other record
+//! of the observer, so that merging two tables simply keeps the record with the highest version.
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
+
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::Cluster;
+use crate::node::{NodeId, NodeStatus};
+
+#[repr(u8)]
+#[derive(
+    Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Hash, TryFromPrimitive, IntoPrimitive,
+)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ReachabilityStatus {
+    Reachable = 0,
+    Unreachable = 1,
+}
+
+/// The latest observation of a member (the subject) by another member (the observer)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ReachabilityRecord {
+    pub observer: NodeId,
+    pub subject: NodeId,
+    pub status: ReachabilityStatus,
+    pub version: u64,
+}
+
+/// The reachability records of all observers, see the [module documentation](self).
+/// Members no observer has recorded anything about are considered reachable.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Reachability {
+    /// The records of each observer, by subject
+    records: HashMap<NodeId, HashMap<NodeId, ReachabilityRecord>>,
+}
+
+impl Reachability {
+    /// Returns all the records of the table
+    pub fn records(&self) -> impl Iterator<Item = &ReachabilityRecord> {
+        self.records.values().flat_map(|records| records.values())
+    }
+
+    /// Returns how an observer considers a subject
+    pub fn status_from(&self, observer: NodeId, subject: NodeId) -> ReachabilityStatus {
+        self.records
+            .get(&observer)
+            .and_then(|records| records.get(&subject))
+            .map_or(ReachabilityStatus::Reachable, |r| r.status)
+    }
+
+    /// Returns how the cluster considers a member: it is unreachable as soon as one observer considers it unreachable
+    pub fn status(&self, subject: NodeId) -> ReachabilityStatus {
+        if self.unreachable_by(subject).is_empty() {
+            ReachabilityStatus::Reachable
+        } else {
+            ReachabilityStatus::Unreachable
+        }
+    }
+
+    /// Returns the observers that consider a member unreachable
+    pub fn unreachable_by(&self, subject: NodeId) -> HashSet<NodeId> {
+        self.records()
+            .filter(|r| r.subject == subject && r.status == ReachabilityStatus::Unreachable)
+            .map(|r| r.observer)
+            .collect()
+    }
+
+    /// Returns the members at least one observer considers unreachable
+    pub fn unreachable_members(&self) -> HashSet<NodeId> {
+        self.records()
+            .filter(|r| r.status == ReachabilityStatus::Unreachable)
+            .map(|r| r.subject)
+            .collect()
+    }
+
+    /// Records how an observer now considers a subject. Returns false if nothing has changed.
+    pub(crate) fn update(
+        &mut self,
+        observer: NodeId,
+        subject: NodeId,
+        status: ReachabilityStatus,
+    ) -> bool {
+        if self.status_from(observer, subject) == status {
+            return false;
+        }
+        let observer_records = self.records.entry(observer).or_default();
+        let version = observer_records
+            .values()
+            .map(|r| r.version)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        observer_records.insert(
+            subject,
+            ReachabilityRecord {
+                observer,
+                subject,
+                status,
+                version,
+            },
+        );
+        true
+    }
+
+    /// Merges records received from another node.
+    /// For every pair of observer and subject, the record with the highest version wins.
+    pub(crate) fn merge(&mut self, incoming: impl IntoIterator<Item = ReachabilityRecord>) {
+        for record in incoming {
+            self.records
+                .entry(record.observer)
+                .or_default()
+                .entry(record.subject)
+                .and_modify(|r| {
+                    if (record.version, record.status) > (r.version, r.status) {
+                        *r = record
+                    }
+                })
+                .or_insert(record);
+        }
+    }
+
+    /// Returns the highest version among the records of each observer
+    pub(crate) fn observer_versions(&self) -> HashMap<NodeId, u64> {
+        self.records
+            .iter()
+            .filter_map(|(observer, records)| {
+                Some((*observer, records.values().map(|r| r.version).max()?))
+            })
+            .collect()
+    }
+
+    /// Removes the records made by a member, and the records about it
+    pub(crate) fn remove_member(&mut self, node_id: NodeId) {
+        self.records.remove(&node_id);
+        for records in self.records.values_mut() {
+            records.remove(&node_id);
+        }
+    }
+
+    /// Returns the records of an observer
+    pub(crate) fn records_of(&self, observer: NodeId) -> impl Iterator<Item = &ReachabilityRecord> {
+        self.records
+            .get(&observer)
+            .into_iter()
+            .flat_map(|records| records.values())
+    }
+}
+
+impl Cluster {
+    /// Returns which members consider which other members unreachable, across the whole cluster
+    pub fn reachability(&self) -> &Reachability {
+        &self.cluster_view.reachability
+    }
+
+    /// Records the opinion of the failure detector of the running node about the members it monitors,
+    /// and escalates the [suspicions](super::swim) that have timed out.
+    /// Returns true if any record has changed.
+    pub(crate) fn update_reachability(&mut self, now: Instant) -> bool {
+        self.track_suspicions(now);
+        let monitored_members = self.failure_detector.monitored_members();
+        let subjects: Vec<NodeId> = self
+            .cluster_view
+            .known_members
+            .values()
+            .filter(|m| {
+                m.id != self.this_node_id
+                    && (monitored_members.contains_key(&m.id)
+                        || self.suspicion_has_expired(m.id, now)
+                        || self
+                            .cluster_view
+                            .reachability
+                            .status_from(self.this_node_id, m.id)
+                            == ReachabilityStatus::Unreachable)
+                    && m.state.as_ref().is_some_and(|s| {
+                        !matches!(s.node_status, NodeStatus::Down | NodeStatus::Removed)
+                    })
+            })
+            .map(|m| m.id)
+            .collect();
+
+        let mut changed = false;
+        for subject in subjects {
+            let live = !monitored_members.contains_key(&subject)
+                || self.failure_detector.is_live(subject, now);
+            let status = if live && !self.suspicion_has_expired(subject, now) {
+                ReachabilityStatus::Reachable
+            } else {
+                ReachabilityStatus::Unreachable
+            };
+            changed |= self
+                .cluster_view
+                .reachability
+                .update(self.this_node_id, subject, status);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quickcheck::Arbitrary;
+
+    use super::*;
+
+    #[quickcheck]
+    fn merge_is_commutative(a: Reachability, b: Reachability) -> bool {
+        let mut merged_a_b = a.clone();
+        merged_a_b.merge(b.records().copied());
+        let mut merged_b_a = b;
+        merged_b_a.merge(a.records().copied());
+        merged_a_b == merged_b_a
+    }
+
+    #[quickcheck]
+    fn merge_is_idempotent(mut a: Reachability, b: Reachability) -> bool {
+        a.merge(b.records().copied());
+        let merged_a_b = a.clone();
+        a.merge(b.records().copied());
+        a == merged_a_b
+    }
+
+    #[test]
+    fn a_member_is_unreachable_as_long_as_one_observer_says_so() {
+        let [a, b, c] = [1, 2, 3].map(|unique_id| NodeId {
+            unique_id,
+            generation: 0,
+        });
+        let mut reachability = Reachability::default();
+        assert_eq!(reachability.status(c), ReachabilityStatus::Reachable);
+        assert!(!reachability.update(a, c, ReachabilityStatus::Reachable));
+
+        assert!(reachability.update(a, c, ReachabilityStatus::Unreachable));
+        assert!(reachability.update(b, c, ReachabilityStatus::Unreachable));
+        assert!(!reachability.update(b, c, ReachabilityStatus::Unreachable));
+        assert_eq!(reachability.unreachable_by(c), HashSet::from([a, b]));
+
+        assert!(reachability.update(a, c, ReachabilityStatus::Reachable));
+        assert_eq!(
+            reachability.status_from(a, c),
+            ReachabilityStatus::Reachable
+        );
+        assert_eq!(reachability.status(c), ReachabilityStatus::Unreachable);
+        assert_eq!(reachability.observer_versions()[&a], 2);
+
+        // Older records lose against newer ones
+        let mut other = Reachability::default();
+        other.merge(reachability.records().copied());
+        other.merge([ReachabilityRecord {
+            observer: a,
+            subject: c,
+            status: ReachabilityStatus::Unreachable,
+            version: 1,
+        }]);
+        assert_eq!(other, reachability);
+
+        assert!(reachability.update(b, c, ReachabilityStatus::Reachable));
+        assert!(reachability.unreachable_members().is_empty());
+    }
+
+    impl Arbitrary for Reachability {
+        fn arbitrary(g: &mut quickcheck::Gen) -> Self {
+            let mut reachability = Reachability::default();
+            reachability.merge(Vec::<ReachabilityRecord>::arbitrary(g));
+            reachability
+        }
+    }
+
+    impl Arbitrary for ReachabilityRecord {
+        fn arbitrary(g: &mut quickcheck::Gen) -> Self {
+            // Pick observers and subjects among a few nodes, so that records often collide
+            let node_ids = [1, 2, 3].map(|unique_id| NodeId {
+                unique_id,
+                generation: 0,
+            });
+            Self {
+                observer: *g.choose(&node_ids).unwrap(),
+                subject: *g.choose(&node_ids).unwrap(),
+                status: *g
+                    .choose(&[
+                        ReachabilityStatus::Reachable,
+                        ReachabilityStatus::Unreachable,
+                    ])
+                    .unwrap(),
+                version: u8::arbitrary(g) as u64,
+            }
+        }
+    }
+}
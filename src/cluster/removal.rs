@@ -0,0 +1,124 @@
+//! Removal of the members that have left the cluster, in the spirit of Akka Cluster.
+//!
+//! Once the cluster has converged, the leader moves [Exiting](NodeStatus::Exiting) and [Down](NodeStatus::Down)
+//! members to [Removed](NodeStatus::Removed). Removed members are then pruned from every view of the running node,
+//! and a tombstone is kept in their place. Tombstones are gossiped along with member views, so that every node
+//! prunes removed members in turn, and ignores any stale view of them it may receive later on.
+//!
+//! Tombstones carry the time of the removal and expire after a while, since by then no stale view is expected to
+//! be circulating anymore.
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use super::Cluster;
+use crate::node::{NodeId, NodeStatus};
+
+impl Cluster {
+    /// Tells whether a member has been removed from the cluster, and is still remembered through its tombstone
+    pub fn is_removed(&self, node_id: NodeId) -> bool {
+        self.cluster_view.tombstones.contains_key(&node_id)
+    }
+
+    /// Merges the tombstones received from another node, and prunes the members they refer to.
+    /// Expired tombstones are ignored, otherwise nodes would keep gossiping them back to each other.
+    /// Removal times ahead of the clock of the running node are brought back to the current time, so that a peer
+    /// whose clock is ahead can't keep tombstones alive for longer than the TTL.
+    /// Returns true if any member has been pruned.
+    pub(crate) fn merge_tombstones(
+        &mut self,
+        incoming: HashMap<NodeId, u64>,
+        now: SystemTime,
+    ) -> bool {
+        let mut changed = false;
+        for (node_id, removed_at) in incoming {
+            let removed_at = removed_at.min(millis_since_epoch(now));
+            if has_expired(removed_at, self.tombstone_ttl, now) {
+                continue;
+            }
+            let tombstone = self
+                .cluster_view
+                .tombstones
+                .entry(node_id)
+                .or_insert(removed_at);
+            *tombstone = std::cmp::max(*tombstone, removed_at);
+            changed |= self.prune(node_id);
+        }
+        changed
+    }
+
+    /// Removes the members the leader has moved to [NodeStatus::Removed], and forgets about expired tombstones
+    /// and [admission denials](super::admission). Quarantined members are forgotten along with their tombstones.
+    /// Members are only removed once every member supports tombstones, otherwise the members that don't would
+    /// gossip them back.
+    /// Returns true if any member has been removed.
+    pub(crate) fn collect_garbage(&mut self, now: SystemTime) -> bool {
+        let prunable = self.all_members_support("tombstones");
+        let removed_members: Vec<NodeId> = self
+            .cluster_view
+            .known_members
+            .values()
+            .filter(|m| {
+                prunable
+                    && m.state
+                        .as_ref()
+                        .is_some_and(|s| s.node_status == NodeStatus::Removed)
+            })
+            .map(|m| m.id)
+            .collect();
+        let removed_at = millis_since_epoch(now);
+        let mut changed = false;
+        for node_id in removed_members {
+            self.cluster_view
+                .tombstones
+                .entry(node_id)
+                .or_insert(removed_at);
+            changed |= self.prune(node_id);
+        }
+
+        let tombstone_ttl = self.tombstone_ttl;
+        self.cluster_view
+            .tombstones
+            .retain(|_, removed_at| !has_expired(*removed_at, tombstone_ttl, now));
+        let cluster_view = &mut self.cluster_view;
+        cluster_view.quarantine.retain(|node_id| {
+            cluster_view.known_members.contains_key(node_id)
+                || cluster_view.tombstones.contains_key(node_id)
+        });
+        self.expire_admission_denials(now);
+        changed
+    }
+
+    /// Prunes a removed member from every view of the running node, except the running node itself:
+    /// it needs its own view to tell that it [has left](Cluster::has_left) the cluster.
+    /// Returns true if the member was known.
+    fn prune(&mut self, node_id: NodeId) -> bool {
+        if node_id == self.this_node_id {
+            return false;
+        }
+        let was_known = self.cluster_view.known_members.contains_key(&node_id);
+        if was_known {
+            log::info!(
+                "[Node id: {}] Member {} has been removed from the cluster",
+                self.this_node_id,
+                node_id
+            );
+        }
+        self.cluster_view.prune(node_id);
+        self.failure_detector.remove(node_id);
+        self.unreachable_since.remove(&node_id);
+        self.admission_denials.remove(&node_id);
+        was_known
+    }
+}
+
+fn has_expired(removed_at: u64, tombstone_ttl: Duration, now: SystemTime) -> bool {
+    millis_since_epoch(now) > removed_at.saturating_add(tombstone_ttl.as_millis() as u64)
+}
+
+fn millis_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
@@ -0,0 +1,273 @@
+//! SWIM-style probing (Das, A., Gupta, I., & Motivala, A. (2002), "SWIM: Scalable Weakly-consistent Infection-style
+//! Process Group Membership Protocol").
+//!
+//! Heartbeats only reach the running node from the few members it monitors, so a single slow link between two nodes
+//! is enough for one of them to consider the other unreachable. When SWIM probing is enabled, the running node also
+//! probes one member per gossip round:
+//! - it pings the member directly, and records the heartbeat carried by its acknowledgement
+//! - if the member doesn't answer in time, it asks a few other members to ping it on its behalf
+//! - if none of them gets an answer either, the member is suspected. The suspicion is gossiped along with the member
+//!   view. If the member doesn't refute the suspicion by increasing its incarnation within the suspicion timeout,
+//!   it is considered unreachable.
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use rand::seq::SliceRandom;
+use url::Url;
+
+use super::{views::MemberViewState, Cluster};
+use crate::node::{NodeId, NodeStatus};
+
+/// Configures SWIM probing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwimSettings {
+    /// How long to wait for the acknowledgement of a direct ping. Indirect pings are given twice as long.
+    /// Defaults to 500ms.
+    pub probe_timeout: Duration,
+    /// How many members to ask to ping a member that didn't acknowledge a direct ping. Defaults to 3.
+    pub indirect_probes: usize,
+    /// How long a member may remain suspected before it is considered unreachable. Defaults to 5s.
+    pub suspicion_timeout: Duration,
+}
+
+impl Default for SwimSettings {
+    fn default() -> Self {
+        Self {
+            probe_timeout: Duration::from_millis(500),
+            indirect_probes: 3,
+            suspicion_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Swim {
+    pub(crate) settings: SwimSettings,
+    /// The members left to probe in the current round. Members are probed in a random order,
+    /// and every member is probed once per round.
+    probe_order: Vec<NodeId>,
+}
+
+impl Swim {
+    pub(crate) fn new(settings: SwimSettings) -> Self {
+        Self {
+            settings,
+            probe_order: Vec::new(),
+        }
+    }
+}
+
+/// The answer of a member to a ping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PingAck {
+    pub(crate) this_node_id: NodeId,
+    pub(crate) heartbeat: u64,
+    pub(crate) incarnation: u64,
+}
+
+/// A member to probe, along with the members to ask for indirect probes if it doesn't answer
+#[derive(Debug, Clone)]
+pub(crate) struct PingTarget {
+    pub(crate) node_id: NodeId,
+    pub(crate) advertised_url: Url,
+    pub(crate) helpers: Vec<Url>,
+}
+
+impl Cluster {
+    /// Selects the next member to probe, if SWIM probing is enabled.
+    /// Only the members that support the `swim` [feature](crate::protocol) are probed, or asked to probe others.
+    pub(crate) fn next_ping_target(&mut self) -> Option<PingTarget> {
+        let probed_members: Vec<NodeId> = self
+            .cluster_view
+            .known_members
+            .values()
+            .filter(|m| {
+                m.id != self.this_node_id
+                    && m.protocol.supports("swim")
+                    && Self::is_probed(m.state.as_ref())
+            })
+            .map(|m| m.id)
+            .collect();
+        let swim = self.swim.as_mut()?;
+
+        let target = loop {
+            if swim.probe_order.is_empty() {
+                if probed_members.is_empty() {
+                    return None;
+                }
+                swim.probe_order = probed_members.clone();
+                swim.probe_order.shuffle(&mut rand::thread_rng());
+            }
+            let candidate = swim.probe_order.pop()?;
+            if probed_members.contains(&candidate) {
+                break candidate;
+            }
+        };
+
+        let mut helpers: Vec<Url> = probed_members
+            .iter()
+            .filter(|id| **id != target)
+            .filter_map(|id| self.cluster_view.known_members.get(id))
+            .map(|m| m.advertised_addr.clone())
+            .collect();
+        helpers.shuffle(&mut rand::thread_rng());
+        helpers.truncate(swim.settings.indirect_probes);
+
+        Some(PingTarget {
+            node_id: target,
+            advertised_url: self.cluster_view.known_members[&target]
+                .advertised_addr
+                .clone(),
+            helpers,
+        })
+    }
+
+    fn is_probed(state: Option<&MemberViewState>) -> bool {
+        state.is_some_and(|s| {
+            !matches!(
+                s.node_status,
+                NodeStatus::Exiting | NodeStatus::Down | NodeStatus::Removed
+            )
+        })
+    }
+
+    /// How long to wait for the acknowledgement of a direct ping, including pings requested by other members
+    pub(crate) fn probe_timeout(&self) -> Duration {
+        self.swim
+            .as_ref()
+            .map_or(SwimSettings::default().probe_timeout, |swim| {
+                swim.settings.probe_timeout
+            })
+    }
+
+    /// How long a member may remain suspected before it is considered unreachable.
+    /// Suspicions are gossiped, so they time out even if SWIM probing is disabled on the running node.
+    fn suspicion_timeout(&self) -> Duration {
+        self.swim
+            .as_ref()
+            .map_or(SwimSettings::default().suspicion_timeout, |swim| {
+                swim.settings.suspicion_timeout
+            })
+    }
+
+    /// Records since when each member has been suspected, and forgets the members that no longer are
+    pub(crate) fn track_suspicions(&mut self, now: Instant) {
+        let suspected_members = self.suspected_members();
+        self.suspected_since
+            .retain(|node_id, _| suspected_members.contains(node_id));
+        for node_id in suspected_members {
+            self.suspected_since.entry(node_id).or_insert(now);
+        }
+    }
+
+    /// Tells whether a member has been suspected for longer than the suspicion timeout
+    pub(crate) fn suspicion_has_expired(&self, node_id: NodeId, now: Instant) -> bool {
+        self.is_suspected(node_id)
+            && self.suspected_since.get(&node_id).is_some_and(|since| {
+                now.saturating_duration_since(*since) >= self.suspicion_timeout()
+            })
+    }
+
+    /// Acknowledges a ping on behalf of the running node
+    pub(crate) fn ping_ack(&self) -> Option<PingAck> {
+        let state = self
+            .cluster_view
+            .known_members
+            .get(&self.this_node_id)?
+            .state
+            .as_ref()?;
+        Some(PingAck {
+            this_node_id: self.this_node_id,
+            heartbeat: state.heartbeat,
+            incarnation: state.incarnation,
+        })
+    }
+
+    /// Handles the outcome of a probe: an acknowledgement counts as a heartbeat, and clears suspicions about older
+    /// incarnations of the member, while the lack of any acknowledgement makes the member suspected.
+    /// Returns true if the view of the member has changed.
+    pub(crate) fn handle_ping_result(&mut self, target: NodeId, ack: Option<PingAck>) -> bool {
+        match ack {
+            Some(ack) if ack.this_node_id == target => {
+                self.failure_detector
+                    .record_heartbeat(target, ack.heartbeat, Instant::now());
+                match self
+                    .cluster_view
+                    .known_members
+                    .get_mut(&target)
+                    .and_then(|m| m.state.as_mut())
+                {
+                    Some(state) => {
+                        let previous_liveness = (state.incarnation, state.suspected);
+                        state.merge_liveness(ack.incarnation, false);
+                        previous_liveness != (state.incarnation, state.suspected)
+                    }
+                    None => false,
+                }
+            }
+            _ => self.suspect(target),
+        }
+    }
+
+    /// Suspects a member of having failed, at its current incarnation, which bumps the version of its view.
+    /// Returns true if the member wasn't already suspected.
+    pub(crate) fn suspect(&mut self, node_id: NodeId) -> bool {
+        match self
+            .cluster_view
+            .known_members
+            .get_mut(&node_id)
+            .and_then(|m| m.state.as_mut())
+        {
+            Some(state) if !state.suspected => {
+                log::info!(
+                    "[Node id: {}] Member {} didn't answer direct nor indirect pings, suspecting it",
+                    self.this_node_id,
+                    node_id
+                );
+                state.suspected = true;
+                state.version = state.version.saturating_add(1);
+                state.observed_by = HashSet::from([self.this_node_id]);
+                self.cluster_view
+                    .version_vector
+                    .record_version(node_id, state.version);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Refutes a suspicion about the running node by increasing its incarnation.
+    /// Returns true if the running node was suspected.
+    pub(crate) fn refute_suspicion(&mut self) -> bool {
+        match self
+            .cluster_view
+            .known_members
+            .get_mut(&self.this_node_id)
+            .and_then(|m| m.state.as_mut())
+        {
+            Some(state) if state.suspected => {
+                log::info!(
+                    "[Node id: {}] Refuting suspicion at incarnation {}",
+                    self.this_node_id,
+                    state.incarnation
+                );
+                state.incarnation += 1;
+                state.suspected = false;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the members currently suspected of having failed
+    pub fn suspected_members(&self) -> HashSet<NodeId> {
+        self.cluster_view
+            .known_members
+            .values()
+            .filter(|m| m.state.as_ref().is_some_and(|s| s.suspected))
+            .map(|m| m.id)
+            .collect()
+    }
+}
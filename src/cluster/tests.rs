@@ -1,12 +1,28 @@
-use std::{collections::HashSet, iter::once};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    iter::once,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
 use quickcheck::Arbitrary;
+use url::Url;
 
 use super::{
-    failure_detector::FailureDetector,
-    views::{ClusterView, MemberView},
+    admission::{Admission, AdmissionHook, AdmissionStage, RequireJoinToken, ADMISSION_DENIAL_TTL},
+    bootstrap::{Bootstrap, BootstrapSettings},
+    convergence::ConvergenceBlocker,
+    downing::AutoDownAfterTimeout,
+    failure_detector::{DeadlineFailureDetector, FailureDetector, PhiAccrualFailureDetector},
+    reachability::ReachabilityStatus,
+    swim::{PingAck, Swim, SwimSettings},
+    views::{ClusterView, MemberView, MemberViewState, PartialClusterView},
     Cluster,
 };
+use crate::{
+    node::{NodeId, NodeStatus},
+    protocol::Protocol,
+};
 
 /// Generates an arbitrary cluster with at least one member
 impl Arbitrary for Cluster {
@@ -14,7 +30,7 @@ impl Arbitrary for Cluster {
         let this_node = MemberView::arbitrary(g);
         let this_node_id = this_node.id;
         let this_advertised_url = this_node.advertised_addr.clone();
-        let mut failure_detector = FailureDetector::new(this_node_id);
+        let mut failure_detector = PhiAccrualFailureDetector::new(Default::default());
         let mut peer_nodes = HashSet::new();
 
         // Generate a cluster view with at least one member
@@ -29,7 +45,7 @@ impl Arbitrary for Cluster {
             }
             if *id != this_node_id {
                 if let Some(state) = &member.state {
-                    failure_detector.record_heartbeat(*id, state.heartbeat);
+                    failure_detector.record_heartbeat(*id, state.heartbeat, Instant::now());
                 }
             }
         }
@@ -37,17 +53,29 @@ impl Arbitrary for Cluster {
         Cluster {
             this_node_id,
             this_advertised_url,
-            failure_detector,
+            failure_detector: Box::new(failure_detector),
             cluster_view,
             unknwon_peer_nodes: peer_nodes.clone(),
             peer_nodes,
+            downing_strategy: None,
+            downing_stable_after: Duration::from_secs(20),
+            unreachable_since: HashMap::new(),
+            suspected_since: HashMap::new(),
+            unreachable_members_changed_at: Instant::now(),
+            bootstrap: None,
+            swim: None,
+            heartbeat_interval: Duration::from_secs(1),
+            monitored_by_nr_of_members: 9,
+            tombstone_ttl: Duration::from_secs(60),
+            admission_hook: None,
+            admission_denials: HashMap::new(),
         }
     }
 }
 
 #[quickcheck]
 fn arbitrary_cluster_test(cluster: Cluster) {
-    assert!(cluster.cluster_view.known_members.len() > 0);
+    assert!(!cluster.cluster_view.known_members.is_empty());
     for (id, member) in cluster
         .cluster_view
         .known_members
@@ -58,11 +86,1133 @@ fn arbitrary_cluster_test(cluster: Cluster) {
             assert_eq!(
                 cluster
                     .failure_detector
-                    .members
-                    .get(&id)
-                    .map(|m| m.last_heartbeat),
+                    .monitored_members()
+                    .get(id)
+                    .copied(),
                 Some(state.heartbeat)
             );
         }
     }
 }
+
+fn single_node_cluster() -> Cluster {
+    single_node_cluster_at("http://localhost:8080")
+}
+
+fn single_node_cluster_at(url: &str) -> Cluster {
+    let this_advertised_url = Url::parse(url).unwrap();
+    let this_node_id = NodeId::from_url(&this_advertised_url, SystemTime::now());
+    Cluster {
+        this_node_id,
+        this_advertised_url: this_advertised_url.clone(),
+        cluster_view: ClusterView::initial(
+            this_node_id,
+            this_advertised_url,
+            BTreeSet::from(["frontend".to_string()]),
+        ),
+        peer_nodes: HashSet::new(),
+        unknwon_peer_nodes: HashSet::new(),
+        failure_detector: Box::new(PhiAccrualFailureDetector::new(Default::default())),
+        downing_strategy: None,
+        downing_stable_after: Duration::from_secs(20),
+        unreachable_since: HashMap::new(),
+        suspected_since: HashMap::new(),
+        unreachable_members_changed_at: Instant::now(),
+        bootstrap: None,
+        swim: None,
+        heartbeat_interval: Duration::from_secs(1),
+        monitored_by_nr_of_members: 9,
+        tombstone_ttl: Duration::from_secs(60),
+        admission_hook: None,
+        admission_denials: HashMap::new(),
+    }
+}
+
+/// Builds a member that is up and has been observed by the running node, with a generation of 0
+fn up_member(this_node_id: NodeId, unique_id: u64, roles: &[&str]) -> MemberView {
+    let id = NodeId {
+        unique_id,
+        generation: 0,
+    };
+    member(this_node_id, id, roles, NodeStatus::Up, 2)
+}
+
+/// Builds a member that has been observed by the running node.
+/// Every incarnation of a node shares the port derived from its unique id.
+fn member(
+    this_node_id: NodeId,
+    id: NodeId,
+    roles: &[&str],
+    node_status: NodeStatus,
+    version: u16,
+) -> MemberView {
+    MemberView {
+        id,
+        advertised_addr: Url::parse(&format!("http://localhost:{}", 9000 + id.unique_id % 1000))
+            .unwrap(),
+        roles: roles.iter().map(|r| r.to_string()).collect(),
+        application_state: BTreeMap::new(),
+        protocol: Protocol::current(),
+        state: Some(MemberViewState {
+            node_status,
+            version,
+            heartbeat: 0,
+            observed_by: HashSet::from([this_node_id, id]),
+            incarnation: 0,
+            suspected: false,
+        }),
+    }
+}
+
+fn member_status(cluster: &Cluster, node_id: NodeId) -> Option<NodeStatus> {
+    cluster
+        .cluster_view
+        .known_members
+        .get(&node_id)
+        .and_then(|m| m.state.as_ref())
+        .map(|s| s.node_status)
+}
+
+/// The leader, if any, must always be a reachable member that is either joining, up or leaving
+#[quickcheck]
+fn leader_is_a_reachable_joining_up_or_leaving_member(cluster: Cluster) -> bool {
+    match cluster.leader() {
+        Some(leader) => {
+            cluster.is_reachable(leader, Instant::now())
+                && matches!(
+                    member_status(&cluster, leader),
+                    Some(NodeStatus::Joining | NodeStatus::Up | NodeStatus::Leaving)
+                )
+        }
+        None => true,
+    }
+}
+
+#[test]
+fn single_node_leader_moves_itself_up() {
+    let mut cluster = single_node_cluster();
+    let this_node_id = cluster.this_node_id;
+
+    assert!(cluster.has_converged());
+    assert_eq!(cluster.leader(), Some(this_node_id));
+    assert_eq!(
+        member_status(&cluster, this_node_id),
+        Some(NodeStatus::Joining)
+    );
+
+    assert!(cluster.perform_leader_actions());
+    assert_eq!(member_status(&cluster, this_node_id), Some(NodeStatus::Up));
+    assert_eq!(
+        cluster
+            .cluster_view
+            .version_vector
+            .versions
+            .get(&this_node_id),
+        Some(&2)
+    );
+
+    // Nothing left to do once the node is up
+    assert!(!cluster.perform_leader_actions());
+}
+
+#[test]
+fn single_node_leaves_the_cluster() {
+    let mut cluster = single_node_cluster();
+    let this_node_id = cluster.this_node_id;
+    assert!(cluster.perform_leader_actions());
+
+    assert!(cluster.mark_own_node_as_leaving());
+    assert_eq!(
+        member_status(&cluster, this_node_id),
+        Some(NodeStatus::Leaving)
+    );
+    assert!(!cluster.has_left());
+
+    // Leaving twice is a no-op
+    assert!(!cluster.mark_own_node_as_leaving());
+
+    // A leaving node still leads the cluster until it has exited
+    assert_eq!(cluster.leader(), Some(this_node_id));
+    assert!(cluster.perform_leader_actions());
+    assert_eq!(
+        member_status(&cluster, this_node_id),
+        Some(NodeStatus::Exiting)
+    );
+    assert!(cluster.has_left());
+}
+
+#[test]
+fn leaving_node_waits_for_other_members_to_observe_its_departure() {
+    let mut cluster = single_node_cluster();
+    let this_node_id = cluster.this_node_id;
+    let other_node_id = NodeId {
+        unique_id: 0,
+        generation: 0,
+    };
+    cluster.cluster_view.merge_member_view(
+        this_node_id,
+        MemberView {
+            id: other_node_id,
+            advertised_addr: Url::parse("http://localhost:8081").unwrap(),
+            roles: BTreeSet::new(),
+            application_state: BTreeMap::new(),
+            protocol: Protocol::current(),
+            state: None,
+        },
+    );
+    cluster
+        .cluster_view
+        .update_member_status(this_node_id, this_node_id, NodeStatus::Exiting);
+    assert!(!cluster.has_left());
+
+    let mut observed_by_everyone = cluster.cluster_view.known_members[&this_node_id].clone();
+    if let Some(state) = observed_by_everyone.state.as_mut() {
+        state.observed_by.insert(other_node_id);
+    }
+    cluster
+        .cluster_view
+        .merge_member_view(this_node_id, observed_by_everyone);
+    assert!(cluster.has_left());
+}
+
+#[test]
+fn leader_ignores_unreachable_members() {
+    let mut cluster = single_node_cluster();
+    let this_node_id = cluster.this_node_id;
+    let other_member = up_member(this_node_id, 0, &[]);
+    let other_node_id = other_member.id;
+    cluster
+        .cluster_view
+        .merge_member_view(this_node_id, other_member);
+
+    // The other node has a lower id, but the failure detector has never heard of it
+    assert!(other_node_id < this_node_id);
+    assert_eq!(cluster.leader(), Some(this_node_id));
+
+    // The cluster cannot converge while a member is unreachable, so the leader stays put
+    assert!(!cluster.has_converged());
+    assert!(!cluster.perform_leader_actions());
+    assert_eq!(
+        member_status(&cluster, this_node_id),
+        Some(NodeStatus::Joining)
+    );
+}
+
+#[test]
+fn down_and_unreachable_members_are_left_out_of_convergence() {
+    let mut cluster = single_node_cluster();
+    let this_node_id = cluster.this_node_id;
+    let now = Instant::now();
+    let [reachable, unreachable, unobserving] = [0, 1, 2].map(|unique_id| {
+        let member = up_member(this_node_id, unique_id, &[]);
+        let id = member.id;
+        cluster.cluster_view.merge_member_view(this_node_id, member);
+        cluster.failure_detector.record_heartbeat(id, 1, now);
+        id
+    });
+    // Every member but the last one has observed every other member
+    let observers = HashSet::from([this_node_id, reachable, unreachable]);
+    for member in cluster.cluster_view.known_members.values_mut() {
+        member.state.as_mut().unwrap().observed_by = observers.clone();
+    }
+    let blockers = |cluster: &Cluster| -> HashSet<ConvergenceBlocker> {
+        cluster.convergence_blockers().into_iter().collect()
+    };
+    assert_eq!(blockers(&cluster).len(), 4);
+    assert!(
+        blockers(&cluster).contains(&ConvergenceBlocker::NotObserved {
+            observer: unobserving,
+            member: this_node_id
+        })
+    );
+
+    // Once downed, a member no longer needs to observe anything
+    cluster
+        .cluster_view
+        .update_member_status(this_node_id, unobserving, NodeStatus::Down);
+    assert!(!cluster.has_converged());
+    for member in cluster.cluster_view.known_members.values_mut() {
+        member.state.as_mut().unwrap().observed_by = observers.clone();
+    }
+    assert!(cluster.has_converged());
+
+    // A member that another member considers unreachable blocks convergence until it is downed
+    cluster.cluster_view.reachability.update(
+        reachable,
+        unreachable,
+        ReachabilityStatus::Unreachable,
+    );
+    assert_eq!(
+        cluster.convergence_blockers(),
+        vec![ConvergenceBlocker::Unreachable(unreachable)]
+    );
+    cluster
+        .cluster_view
+        .update_member_status(this_node_id, unreachable, NodeStatus::Down);
+    for member in cluster.cluster_view.known_members.values_mut() {
+        member.state.as_mut().unwrap().observed_by = HashSet::from([this_node_id, reachable]);
+    }
+    assert!(cluster.has_converged());
+}
+
+#[test]
+fn leader_downs_unreachable_members_once_stable() {
+    let mut cluster = single_node_cluster();
+    let this_node_id = cluster.this_node_id;
+    let other_member = up_member(this_node_id, 0, &[]);
+    let other_node_id = other_member.id;
+    cluster
+        .cluster_view
+        .merge_member_view(this_node_id, other_member);
+    cluster.downing_strategy = Some(Arc::new(AutoDownAfterTimeout {
+        timeout: Duration::ZERO,
+    }));
+    cluster.downing_stable_after = Duration::from_secs(5);
+
+    let now = Instant::now();
+    assert!(!cluster.perform_downing(now));
+    assert_eq!(cluster.unreachable_since(other_node_id), Some(now));
+    assert_eq!(member_status(&cluster, other_node_id), Some(NodeStatus::Up));
+
+    assert!(cluster.perform_downing(now + Duration::from_secs(5)));
+    assert_eq!(
+        member_status(&cluster, other_node_id),
+        Some(NodeStatus::Down)
+    );
+
+    // Down members are no longer tracked
+    assert!(!cluster.perform_downing(now + Duration::from_secs(10)));
+    assert_eq!(cluster.unreachable_since(other_node_id), None);
+}
+
+#[test]
+fn role_aware_queries() {
+    let mut cluster = single_node_cluster();
+    let this_node_id = cluster.this_node_id;
+    let worker = up_member(this_node_id, 1, &["worker"]);
+    let worker_and_storage = up_member(this_node_id, 2, &["worker", "storage"]);
+    let joining_worker = member(
+        this_node_id,
+        NodeId {
+            unique_id: 3,
+            generation: 0,
+        },
+        &["worker"],
+        NodeStatus::Joining,
+        2,
+    );
+    for member in [&worker, &worker_and_storage, &joining_worker] {
+        cluster
+            .cluster_view
+            .merge_member_view(this_node_id, member.clone());
+    }
+
+    let ids = |members: Vec<&MemberView>| members.into_iter().map(|m| m.id).collect::<HashSet<_>>();
+    assert_eq!(
+        ids(cluster.members_with_role("worker").collect()),
+        HashSet::from([worker.id, worker_and_storage.id, joining_worker.id])
+    );
+    assert_eq!(
+        ids(cluster.up_members_with_role("worker").collect()),
+        HashSet::from([worker.id, worker_and_storage.id])
+    );
+    assert_eq!(
+        ids(cluster.up_members_with_role("storage").collect()),
+        HashSet::from([worker_and_storage.id])
+    );
+    assert_eq!(
+        ids(cluster.members_with_role("frontend").collect()),
+        HashSet::from([this_node_id])
+    );
+    assert!(cluster.up_members_with_role("frontend").next().is_none());
+}
+
+#[test]
+fn peers_cannot_grant_roles_to_known_members() {
+    let mut cluster = single_node_cluster();
+    let this_node_id = cluster.this_node_id;
+    let worker = up_member(this_node_id, 1, &["worker"]);
+    let id = worker.id;
+    cluster.cluster_view.merge_member_view(this_node_id, worker);
+
+    cluster.cluster_view.merge_member_view(
+        this_node_id,
+        member(this_node_id, id, &["admin", "worker"], NodeStatus::Up, 3),
+    );
+    let roles = &cluster.cluster_view.known_members[&id].roles;
+    assert_eq!(roles, &BTreeSet::from(["worker".to_string()]));
+}
+
+#[test]
+fn application_state_is_merged_by_version() {
+    let mut cluster = single_node_cluster();
+    let this_node_id = cluster.this_node_id;
+
+    assert!(cluster.set_local_state("load".to_string(), "0.5".to_string()));
+    assert!(!cluster.set_local_state("load".to_string(), "0.5".to_string()));
+    assert!(cluster.set_local_state("ready".to_string(), "true".to_string()));
+    assert!(cluster.set_local_state("load".to_string(), "0.7".to_string()));
+
+    let this_node = &cluster.cluster_view.known_members[&this_node_id];
+    assert_eq!(this_node.application_state_value("load"), Some("0.7"));
+    assert_eq!(this_node.application_state["load"].version, 3);
+    assert_eq!(this_node.application_state["ready"].version, 2);
+
+    let mut other_node = up_member(this_node_id, 0, &[]);
+    other_node.set_application_state("shards".to_string(), "12".to_string());
+    let stale_other_node = other_node.clone();
+    other_node.set_application_state("shards".to_string(), "16".to_string());
+
+    cluster
+        .cluster_view
+        .merge_member_view(this_node_id, other_node.clone());
+    cluster
+        .cluster_view
+        .merge_member_view(this_node_id, stale_other_node);
+    assert_eq!(
+        cluster.cluster_view.known_members[&other_node.id].application_state_value("shards"),
+        Some("16")
+    );
+}
+
+/// Plays one digest-first exchange initiated by `initiator`, the way the main loops of both nodes would
+fn exchange_digests(initiator: &mut Cluster, responder: &mut Cluster) -> usize {
+    let initiator_digest = initiator.digest();
+    responder.record_digest_heartbeats(&initiator_digest);
+    let (responder_digest, delta) = (responder.digest(), responder.delta_for(&initiator_digest));
+    let mut shipped_members = delta.members.len();
+    initiator.merge_tombstones(delta.tombstones, SystemTime::now());
+
+    for member in delta.members.into_values() {
+        initiator
+            .cluster_view
+            .merge_member_view(initiator.this_node_id, member);
+    }
+    initiator.cluster_view.merge_quarantine(delta.quarantine);
+    initiator
+        .cluster_view
+        .reachability
+        .merge(delta.reachability);
+    initiator.record_digest_heartbeats(&responder_digest);
+    let delta = initiator.delta_for(&responder_digest);
+    shipped_members += delta.members.len();
+    responder.merge_tombstones(delta.tombstones, SystemTime::now());
+    for member in delta.members.into_values() {
+        responder
+            .cluster_view
+            .merge_member_view(responder.this_node_id, member);
+    }
+    responder.cluster_view.merge_quarantine(delta.quarantine);
+    responder
+        .cluster_view
+        .reachability
+        .merge(delta.reachability);
+    shipped_members
+}
+
+#[test]
+fn digest_exchanges_only_ship_outdated_members() {
+    let mut a = single_node_cluster_at("http://localhost:8080");
+    let mut b = single_node_cluster_at("http://localhost:8081");
+
+    // Both nodes are unknown to each other, then each must observe the other's view
+    assert!(exchange_digests(&mut a, &mut b) > 0);
+    exchange_digests(&mut a, &mut b);
+    assert_eq!(a.cluster_view.known_members, b.cluster_view.known_members);
+    assert_eq!(exchange_digests(&mut a, &mut b), 0);
+
+    // Heartbeats travel in digests, without shipping any member view
+    b.increment_own_heartbeat();
+    assert_eq!(exchange_digests(&mut a, &mut b), 0);
+    assert_eq!(
+        a.cluster_view.heartbeats[&b.this_node_id],
+        b.cluster_view.heartbeats[&b.this_node_id]
+    );
+
+    // Status changes ship the affected members, which come back with their new observer
+    assert!(a.perform_leader_actions() || b.perform_leader_actions());
+    assert_eq!(exchange_digests(&mut b, &mut a), 4);
+    assert_eq!(exchange_digests(&mut b, &mut a), 0);
+
+    // Application state changes don't affect observers, so they are shipped once
+    assert!(b.set_local_state("load".to_string(), "0.5".to_string()));
+    assert_eq!(exchange_digests(&mut b, &mut a), 1);
+    assert_eq!(
+        a.cluster_view.known_members[&b.this_node_id].application_state_value("load"),
+        Some("0.5")
+    );
+}
+
+fn bootstrapping_cluster_at(url: &str) -> Cluster {
+    let contact_points = [
+        "http://localhost:8080",
+        "http://localhost:8081",
+        "http://localhost:8082",
+    ]
+    .map(|url| Url::parse(url).unwrap());
+    let mut cluster = single_node_cluster_at(url);
+    cluster.bootstrap = Some(Bootstrap::new(BootstrapSettings::new(contact_points)));
+    cluster
+}
+
+fn probe_answers(answers: &[(&str, bool)]) -> HashMap<Url, bool> {
+    answers
+        .iter()
+        .map(|(url, cluster_formed)| (Url::parse(url).unwrap(), *cluster_formed))
+        .collect()
+}
+
+#[test]
+fn bootstrapping_node_with_the_lowest_url_forms_the_cluster_once_contacts_are_stable() {
+    let mut cluster = bootstrapping_cluster_at("http://localhost:8080");
+    let this_node_id = cluster.this_node_id;
+    let now = Instant::now();
+    let answers = probe_answers(&[
+        ("http://localhost:8081", false),
+        ("http://localhost:8082", false),
+    ]);
+
+    // A bootstrapping node doesn't move itself up on its own
+    assert!(!cluster.perform_leader_actions());
+    assert!(!cluster.handle_probe_answers(answers.clone(), now));
+    assert!(!cluster.handle_probe_answers(answers.clone(), now + Duration::from_secs(1)));
+    assert!(!cluster.is_formed());
+
+    assert!(cluster.handle_probe_answers(answers, now + Duration::from_secs(5)));
+    assert_eq!(member_status(&cluster, this_node_id), Some(NodeStatus::Up));
+    assert!(cluster.is_formed());
+    assert!(cluster.contact_points_to_probe().is_empty());
+}
+
+#[test]
+fn bootstrapping_node_waits_for_the_required_contact_points() {
+    let mut cluster = bootstrapping_cluster_at("http://localhost:8080");
+    let now = Instant::now();
+    let answers = probe_answers(&[("http://localhost:8081", false)]);
+
+    assert!(!cluster.handle_probe_answers(answers.clone(), now));
+    assert!(!cluster.handle_probe_answers(answers, now + Duration::from_secs(60)));
+    assert!(!cluster.is_formed());
+}
+
+#[test]
+fn bootstrapping_nodes_join_the_cluster_formed_by_a_contact_point() {
+    let mut cluster = bootstrapping_cluster_at("http://localhost:8081");
+    let this_node_id = cluster.this_node_id;
+    let now = Instant::now();
+    let answers = probe_answers(&[
+        ("http://localhost:8080", false),
+        ("http://localhost:8082", false),
+    ]);
+
+    // Only the contact point with the lowest URL forms the cluster
+    assert!(!cluster.handle_probe_answers(answers.clone(), now));
+    assert!(!cluster.handle_probe_answers(answers, now + Duration::from_secs(5)));
+    assert!(!cluster.perform_leader_actions());
+    assert_eq!(
+        member_status(&cluster, this_node_id),
+        Some(NodeStatus::Joining)
+    );
+
+    let answers = probe_answers(&[
+        ("http://localhost:8080", true),
+        ("http://localhost:8082", false),
+    ]);
+    assert!(cluster.handle_probe_answers(answers, now + Duration::from_secs(6)));
+    assert!(cluster
+        .unknwon_peer_nodes
+        .contains(&Url::parse("http://localhost:8080").unwrap()));
+    assert!(cluster.contact_points_to_probe().is_empty());
+    assert_eq!(
+        member_status(&cluster, this_node_id),
+        Some(NodeStatus::Joining)
+    );
+}
+
+#[test]
+fn swim_probes_every_member_once_per_round() {
+    let mut cluster = single_node_cluster();
+    let this_node_id = cluster.this_node_id;
+    assert!(cluster.next_ping_target().is_none());
+
+    cluster.swim = Some(Swim::new(SwimSettings {
+        indirect_probes: 1,
+        ..Default::default()
+    }));
+    assert!(cluster.next_ping_target().is_none());
+
+    let members: HashSet<NodeId> = (0..3)
+        .map(|unique_id| {
+            let member = up_member(this_node_id, unique_id, &[]);
+            let id = member.id;
+            cluster.cluster_view.merge_member_view(this_node_id, member);
+            id
+        })
+        .collect();
+    for _ in 0..2 {
+        let probed: HashSet<NodeId> = (0..3)
+            .map(|_| {
+                let target = cluster.next_ping_target().unwrap();
+                assert_eq!(target.helpers.len(), 1);
+                assert!(!target.helpers.contains(&target.advertised_url));
+                target.node_id
+            })
+            .collect();
+        assert_eq!(probed, members);
+    }
+}
+
+#[test]
+fn suspected_members_become_unreachable_unless_they_refute_the_suspicion_in_time() {
+    let mut cluster = single_node_cluster();
+    cluster.swim = Some(Swim::new(SwimSettings {
+        suspicion_timeout: Duration::from_secs(1),
+        ..Default::default()
+    }));
+    let this_node_id = cluster.this_node_id;
+    let mut other_node = single_node_cluster_at("http://localhost:8081");
+    let other_node_id = other_node.this_node_id;
+    cluster.cluster_view.merge_member_view(
+        this_node_id,
+        other_node.cluster_view.known_members[&other_node_id].clone(),
+    );
+    let now = Instant::now();
+    cluster
+        .failure_detector
+        .record_heartbeat(other_node_id, 1, now);
+    assert!(cluster.is_reachable(other_node_id, now));
+    let version = cluster.cluster_view.version_vector.versions[&other_node_id];
+
+    // Neither direct nor indirect pings were acknowledged
+    assert!(cluster.handle_ping_result(other_node_id, None));
+    assert_eq!(cluster.suspected_members(), HashSet::from([other_node_id]));
+    assert_eq!(
+        cluster.cluster_view.version_vector.versions[&other_node_id],
+        version + 1
+    );
+    assert!(!cluster.update_reachability(now));
+    assert!(cluster.is_reachable(other_node_id, now));
+
+    // An acknowledgement from the same incarnation doesn't clear the suspicion
+    let ack = PingAck {
+        this_node_id: other_node_id,
+        heartbeat: 2,
+        incarnation: 0,
+    };
+    assert!(!cluster.handle_ping_result(other_node_id, Some(ack)));
+
+    // The suspicion times out, and the member is reported unreachable
+    let timed_out = now + Duration::from_secs(1);
+    assert!(!cluster.is_reachable(other_node_id, timed_out));
+    assert!(cluster.update_reachability(timed_out));
+    assert_eq!(
+        cluster
+            .reachability()
+            .status_from(this_node_id, other_node_id),
+        ReachabilityStatus::Unreachable
+    );
+
+    // The suspected node learns about the suspicion through gossip, and refutes it
+    other_node.cluster_view.merge_member_view(
+        other_node_id,
+        cluster.cluster_view.known_members[&other_node_id].clone(),
+    );
+    assert!(other_node.refute_suspicion());
+    assert!(!other_node.refute_suspicion());
+    cluster.cluster_view.merge_member_view(
+        this_node_id,
+        other_node.cluster_view.known_members[&other_node_id].clone(),
+    );
+    assert!(cluster.suspected_members().is_empty());
+    assert!(cluster.is_reachable(other_node_id, timed_out));
+    assert!(cluster.update_reachability(timed_out));
+    assert_eq!(
+        cluster.reachability().status(other_node_id),
+        ReachabilityStatus::Reachable
+    );
+
+    // An acknowledgement carrying a newer incarnation clears a suspicion as well
+    assert!(cluster.suspect(other_node_id));
+    let ack = PingAck {
+        this_node_id: other_node_id,
+        heartbeat: 3,
+        incarnation: 2,
+    };
+    assert!(cluster.handle_ping_result(other_node_id, Some(ack)));
+    assert!(cluster.suspected_members().is_empty());
+}
+
+#[test]
+fn heartbeats_are_sent_to_the_next_members_on_the_ring() {
+    let mut cluster = single_node_cluster();
+    let this_node_id = cluster.this_node_id;
+    assert!(cluster.heartbeat_receivers().is_empty());
+
+    // Members with a lower unique id than the running node come after it on the ring
+    let members: Vec<NodeId> = (0..5)
+        .map(|unique_id| {
+            let member = up_member(this_node_id, unique_id, &[]);
+            let id = member.id;
+            cluster.cluster_view.merge_member_view(this_node_id, member);
+            id
+        })
+        .collect();
+    assert!(members.iter().all(|id| *id < this_node_id));
+    let url = |port: u16| Url::parse(&format!("http://localhost:{}", port)).unwrap();
+
+    cluster.monitored_by_nr_of_members = 2;
+    assert_eq!(cluster.heartbeat_receivers(), vec![url(9000), url(9001)]);
+
+    // Down members are skipped
+    cluster
+        .cluster_view
+        .update_member_status(this_node_id, members[0], NodeStatus::Down);
+    assert_eq!(cluster.heartbeat_receivers(), vec![url(9001), url(9002)]);
+
+    cluster.monitored_by_nr_of_members = 9;
+    assert_eq!(cluster.heartbeat_receivers().len(), 4);
+}
+
+#[test]
+fn reachability_observations_are_gossiped() {
+    let mut a = single_node_cluster_at("http://localhost:8080");
+    let mut b = single_node_cluster_at("http://localhost:8081");
+    let mut c = single_node_cluster_at("http://localhost:8082");
+    exchange_digests(&mut a, &mut b);
+    exchange_digests(&mut a, &mut b);
+    exchange_digests(&mut c, &mut a);
+    exchange_digests(&mut c, &mut a);
+    let (a_id, b_id, c_id) = (a.this_node_id, b.this_node_id, c.this_node_id);
+
+    // a stops hearing from c, while b keeps hearing from it
+    let now = Instant::now();
+    a.failure_detector = Box::new(DeadlineFailureDetector::new(
+        Duration::from_secs(1),
+        Duration::from_secs(1),
+    ));
+    a.failure_detector.record_heartbeat(b_id, 1, now);
+    a.failure_detector
+        .record_heartbeat(c_id, 1, now - Duration::from_secs(10));
+    assert!(a.update_reachability(now));
+    assert!(!a.update_reachability(now));
+    assert_eq!(
+        a.reachability().status_from(a_id, b_id),
+        ReachabilityStatus::Reachable
+    );
+
+    exchange_digests(&mut b, &mut a);
+    assert_eq!(b.reachability(), a.reachability());
+    assert_eq!(b.reachability().unreachable_by(c_id), HashSet::from([a_id]));
+    assert_eq!(
+        b.reachability().unreachable_members(),
+        HashSet::from([c_id])
+    );
+
+    // Once a hears from c again, the new record replaces the old one everywhere
+    a.failure_detector.record_heartbeat(c_id, 2, now);
+    assert!(a.update_reachability(now));
+    exchange_digests(&mut a, &mut b);
+    assert_eq!(b.reachability().status(c_id), ReachabilityStatus::Reachable);
+}
+
+#[test]
+fn removed_members_are_pruned_and_cannot_be_resurrected() {
+    let mut a = single_node_cluster_at("http://localhost:8080");
+    let mut b = single_node_cluster_at("http://localhost:8081");
+    exchange_digests(&mut a, &mut b);
+    exchange_digests(&mut a, &mut b);
+    let (mut leader, mut follower) = if a.is_leader() { (a, b) } else { (b, a) };
+    let follower_id = follower.this_node_id;
+    assert!(leader.perform_leader_actions());
+    exchange_digests(&mut leader, &mut follower);
+
+    // The leader moves the leaving member to exiting, then removes it
+    assert!(follower.mark_own_node_as_leaving());
+    exchange_digests(&mut follower, &mut leader);
+    assert!(leader.perform_leader_actions());
+    assert_eq!(
+        member_status(&leader, follower_id),
+        Some(NodeStatus::Exiting)
+    );
+    assert!(leader.perform_leader_actions());
+    assert_eq!(
+        member_status(&leader, follower_id),
+        Some(NodeStatus::Removed)
+    );
+
+    let now = SystemTime::now();
+    assert!(leader.collect_garbage(now));
+    assert!(leader.is_removed(follower_id));
+    assert!(!leader.cluster_view.known_members.contains_key(&follower_id));
+    assert!(!leader.cluster_view.heartbeats.contains_key(&follower_id));
+    assert!(!leader
+        .cluster_view
+        .version_vector
+        .versions
+        .contains_key(&follower_id));
+    assert!(!leader
+        .failure_detector
+        .monitored_members()
+        .contains_key(&follower_id));
+    assert!(leader.cluster_view.known_members.values().all(|m| !m
+        .state
+        .as_ref()
+        .unwrap()
+        .observed_by
+        .contains(&follower_id)));
+
+    // Stale views of the removed member are ignored, and the member learns about its removal
+    assert!(!follower.has_left());
+    exchange_digests(&mut follower, &mut leader);
+    assert!(!leader.cluster_view.known_members.contains_key(&follower_id));
+    assert!(follower.has_left());
+
+    // Tombstones are eventually forgotten
+    assert!(!leader.collect_garbage(now + Duration::from_secs(61)));
+    assert!(!leader.is_removed(follower_id));
+}
+
+#[test]
+fn tombstones_from_the_future_expire_after_the_ttl() {
+    let mut cluster = single_node_cluster();
+    let removed_id = up_member(cluster.this_node_id, 1, &[]).id;
+    let now = SystemTime::now();
+    let a_day_from_now = now + Duration::from_secs(24 * 60 * 60);
+    let removed_at = a_day_from_now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+
+    cluster.merge_tombstones(HashMap::from([(removed_id, removed_at)]), now);
+    assert!(cluster.is_removed(removed_id));
+    cluster.collect_garbage(now + Duration::from_secs(61));
+    assert!(!cluster.is_removed(removed_id));
+}
+
+#[test]
+fn downed_members_are_quarantined_until_they_restart() {
+    let mut a = single_node_cluster_at("http://localhost:8080");
+    let mut b = single_node_cluster_at("http://localhost:8081");
+    exchange_digests(&mut a, &mut b);
+    let wedged = up_member(a.this_node_id, 1, &[]);
+    a.cluster_view
+        .merge_member_view(a.this_node_id, wedged.clone());
+    assert!(a.down(wedged.id));
+    assert!(!a.down(wedged.id));
+    assert!(a.is_quarantined(wedged.id));
+
+    // Stale views of the downed member can't bring it back, whatever their version
+    let stale = member(a.this_node_id, wedged.id, &[], NodeStatus::Up, 10);
+    b.cluster_view
+        .merge_member_view(b.this_node_id, stale.clone());
+    assert_eq!(member_status(&b, wedged.id), Some(NodeStatus::Up));
+    exchange_digests(&mut a, &mut b);
+    assert!(b.is_quarantined(wedged.id));
+    assert_eq!(member_status(&b, wedged.id), Some(NodeStatus::Down));
+    a.cluster_view.merge_member_view(a.this_node_id, stale);
+    assert_eq!(member_status(&a, wedged.id), Some(NodeStatus::Down));
+
+    // Once restarted, the node has a newer generation and can join again
+    let restarted_id = NodeId {
+        generation: wedged.id.generation + 1,
+        ..wedged.id
+    };
+    let restarted = member(a.this_node_id, restarted_id, &[], NodeStatus::Up, 2);
+    a.cluster_view
+        .merge_member_view(a.this_node_id, restarted.clone());
+    assert!(!a.is_quarantined(restarted.id));
+    assert_eq!(member_status(&a, restarted.id), Some(NodeStatus::Up));
+}
+
+#[test]
+fn quarantines_only_apply_to_downed_members_and_expire_with_their_tombstones() {
+    let mut cluster = single_node_cluster();
+    let this_node_id = cluster.this_node_id;
+    let downed = up_member(this_node_id, 1, &[]);
+    cluster
+        .cluster_view
+        .merge_member_view(this_node_id, downed.clone());
+
+    // A peer can't down a member, nor the running node, through its quarantine alone
+    cluster
+        .cluster_view
+        .merge_quarantine(HashSet::from([downed.id, this_node_id]));
+    assert!(!cluster.is_quarantined(downed.id));
+    assert!(!cluster.is_quarantined(this_node_id));
+    assert_eq!(member_status(&cluster, downed.id), Some(NodeStatus::Up));
+
+    // Stale views of a quarantined member are overridden with a newer version, observed by the running node only
+    assert!(cluster.down(downed.id));
+    let mut stale = member(this_node_id, downed.id, &[], NodeStatus::Up, 10);
+    stale.state.as_mut().unwrap().observed_by = HashSet::from([downed.id]);
+    cluster.cluster_view.merge_member_view(this_node_id, stale);
+    let state = cluster.cluster_view.known_members[&downed.id]
+        .state
+        .clone()
+        .unwrap();
+    assert_eq!(state.node_status, NodeStatus::Down);
+    assert_eq!(state.version, 11);
+    assert_eq!(state.observed_by, HashSet::from([this_node_id]));
+
+    // The quarantine is forgotten along with the tombstone of the member
+    let now = SystemTime::now();
+    cluster
+        .cluster_view
+        .update_member_status(this_node_id, downed.id, NodeStatus::Removed);
+    cluster.collect_garbage(now);
+    assert!(cluster.is_removed(downed.id));
+    assert!(cluster.is_quarantined(downed.id));
+    cluster.collect_garbage(now + Duration::from_secs(61));
+    assert!(!cluster.is_removed(downed.id));
+    assert!(!cluster.is_quarantined(downed.id));
+}
+
+#[test]
+fn leader_downs_previous_incarnations_once_restarted_members_are_up() {
+    let mut cluster = single_node_cluster();
+    let this_node_id = cluster.this_node_id;
+    // The restarted member has the highest id, so that the running node leads the cluster whenever `other` is
+    // unreachable
+    let previous = up_member(this_node_id, u64::MAX, &[]);
+    let other = up_member(this_node_id, 2, &[]);
+    let restarted_id = NodeId {
+        generation: previous.id.generation + 1,
+        ..previous.id
+    };
+    let restarted = member(this_node_id, restarted_id, &[], NodeStatus::Joining, 2);
+    for member in [previous.clone(), other.clone(), restarted.clone()] {
+        cluster.cluster_view.merge_member_view(this_node_id, member);
+    }
+    // The previous incarnation has stopped, so it is unreachable, but it doesn't prevent convergence
+    cluster
+        .failure_detector
+        .record_heartbeat(restarted.id, 0, Instant::now());
+    assert!(!cluster
+        .convergence_blockers()
+        .contains(&ConvergenceBlocker::Unreachable(previous.id)));
+
+    // Not until the newer incarnation is up
+    assert!(!cluster.down_restarted_members(Instant::now()));
+    cluster
+        .cluster_view
+        .update_member_status(this_node_id, restarted.id, NodeStatus::Up);
+
+    // Only the leader downs previous incarnations
+    cluster
+        .failure_detector
+        .record_heartbeat(other.id, 0, Instant::now());
+    assert!(!cluster.is_leader());
+    assert!(!cluster.down_restarted_members(Instant::now()));
+    cluster.failure_detector.remove(other.id);
+    assert!(cluster.is_leader());
+
+    assert!(cluster.down_restarted_members(Instant::now()));
+    assert_eq!(member_status(&cluster, previous.id), Some(NodeStatus::Down));
+    assert!(cluster.is_quarantined(previous.id));
+    assert_eq!(member_status(&cluster, restarted.id), Some(NodeStatus::Up));
+    assert_eq!(member_status(&cluster, other.id), Some(NodeStatus::Up));
+    assert!(!cluster.down_restarted_members(Instant::now()));
+}
+
+#[test]
+fn members_restarted_by_an_older_build_replace_their_previous_incarnation() {
+    let mut cluster = single_node_cluster();
+    let this_node_id = cluster.this_node_id;
+    // Newer builds count generations in nanoseconds, older ones in seconds: the member is rolled back a minute later
+    let previous_id = NodeId {
+        unique_id: u64::MAX,
+        generation: 1_700_000_000_000_000_000,
+    };
+    let previous = member(this_node_id, previous_id, &[], NodeStatus::Up, 2);
+    let restarted_id = NodeId {
+        generation: 1_700_000_060,
+        ..previous.id
+    };
+    let restarted = member(this_node_id, restarted_id, &[], NodeStatus::Up, 2);
+    for member in [previous.clone(), restarted.clone()] {
+        cluster.cluster_view.merge_member_view(this_node_id, member);
+    }
+    cluster
+        .failure_detector
+        .record_heartbeat(restarted.id, 0, Instant::now());
+    assert!(cluster.has_restarted(previous.id));
+    assert!(!cluster.has_restarted(restarted.id));
+
+    assert!(cluster.down_restarted_members(Instant::now()));
+    assert_eq!(member_status(&cluster, previous.id), Some(NodeStatus::Down));
+    assert_eq!(member_status(&cluster, restarted.id), Some(NodeStatus::Up));
+}
+
+#[test]
+fn features_are_enabled_once_every_member_supports_them() {
+    let mut cluster = single_node_cluster();
+    let this_node_id = cluster.this_node_id;
+    assert!(cluster.all_members_support("tombstones"));
+
+    let older_member = MemberView {
+        protocol: Protocol {
+            version: 1,
+            features: BTreeSet::from(["swim".to_string()]),
+        },
+        ..up_member(this_node_id, 0, &[])
+    };
+    cluster
+        .cluster_view
+        .merge_member_view(this_node_id, older_member);
+    assert!(cluster.all_members_support("swim"));
+    assert!(!cluster.all_members_support("tombstones"));
+}
+
+#[test]
+fn members_predating_a_feature_are_left_out_of_it() {
+    let mut cluster = single_node_cluster();
+    cluster.swim = Some(Swim::new(SwimSettings::default()));
+    let this_node_id = cluster.this_node_id;
+    // A node built before protocol versioning publishes no protocol
+    let baseline_member = MemberView {
+        protocol: Protocol::default(),
+        ..up_member(this_node_id, 0, &[])
+    };
+    let baseline_id = baseline_member.id;
+    cluster
+        .cluster_view
+        .merge_member_view(this_node_id, baseline_member);
+
+    assert!(cluster.heartbeat_receivers().is_empty());
+    assert!(cluster.next_ping_target().is_none());
+    let destinations = cluster.select_gossip_destinations();
+    assert_eq!(destinations.len(), 1);
+    assert!(destinations[0].digest.is_none());
+    assert!(destinations[0].full_view.is_some());
+
+    // Removed members are kept until every remaining member supports tombstones
+    let removed_id = up_member(this_node_id, 1, &[]).id;
+    cluster
+        .cluster_view
+        .merge_member_view(this_node_id, up_member(this_node_id, 1, &[]));
+    cluster
+        .cluster_view
+        .update_member_status(this_node_id, removed_id, NodeStatus::Removed);
+    assert!(!cluster.collect_garbage(SystemTime::now()));
+    assert!(!cluster.is_removed(removed_id));
+
+    cluster
+        .cluster_view
+        .update_member_status(this_node_id, baseline_id, NodeStatus::Removed);
+    assert!(cluster.collect_garbage(SystemTime::now()));
+    assert!(cluster.is_removed(removed_id));
+    assert!(cluster.is_removed(baseline_id));
+}
+
+#[test]
+fn nodes_denied_admission_are_ignored_along_with_their_views() {
+    let mut cluster = single_node_cluster();
+    cluster.admission_hook = Some(Arc::new(RequireJoinToken {
+        tokens: vec!["secret".to_string()],
+    }));
+    let this_node_id = cluster.this_node_id;
+    let intruder = up_member(this_node_id, 1, &[]);
+    let joiner = up_member(this_node_id, 2, &[]);
+    let relayed = up_member(this_node_id, 3, &[]);
+    let view = |sender: NodeId, join_token: Option<&str>| PartialClusterView {
+        this_node_id: sender,
+        members: HashMap::from([
+            (intruder.id, intruder.clone()),
+            (joiner.id, joiner.clone()),
+            (relayed.id, relayed.clone()),
+        ]),
+        reachability: Vec::new(),
+        tombstones: HashMap::new(),
+        quarantine: HashSet::new(),
+        join_token: join_token.map(str::to_string),
+    };
+    let now = SystemTime::now();
+
+    assert!(!cluster.filter_admitted_members(&mut view(intruder.id, None), now));
+    assert_eq!(
+        cluster.admission_denial(intruder.id),
+        Some("missing join token")
+    );
+    // The intruder can't borrow the token of another node by relaying it
+    let mut relayed_by_intruder = view(intruder.id, None);
+    relayed_by_intruder.members.remove(&intruder.id);
+    assert!(!cluster.filter_admitted_members(&mut relayed_by_intruder, now));
+
+    // Members relayed by an admitted sender have presented their token to it
+    let mut incoming = view(joiner.id, Some("secret"));
+    assert!(cluster.filter_admitted_members(&mut incoming, now));
+    assert_eq!(
+        incoming.members.keys().collect::<HashSet<_>>(),
+        HashSet::from([&joiner.id, &relayed.id])
+    );
+    assert_eq!(cluster.admission_denial(joiner.id), None);
+
+    // Denials are forgotten after a while, so that denied nodes can present a valid token later on
+    cluster.collect_garbage(now + ADMISSION_DENIAL_TTL);
+    assert_eq!(cluster.admission_denial(intruder.id), None);
+    assert!(cluster.filter_admitted_members(&mut view(intruder.id, Some("secret")), now));
+}
+
+#[derive(Debug)]
+struct RequireReadinessToMoveUp;
+
+impl AdmissionHook for RequireReadinessToMoveUp {
+    fn admit(
+        &self,
+        stage: AdmissionStage,
+        candidate: &MemberView,
+        _join_token: Option<&str>,
+    ) -> Admission {
+        match stage {
+            AdmissionStage::MovingUp
+                if candidate.application_state_value("ready") != Some("true") =>
+            {
+                Admission::Deny("not ready".to_string())
+            }
+            _ => Admission::Accept,
+        }
+    }
+}
+
+#[test]
+fn leader_downs_members_denied_admission_when_moving_them_up() {
+    let mut cluster = single_node_cluster();
+    cluster.admission_hook = Some(Arc::new(RequireReadinessToMoveUp));
+    let this_node_id = cluster.this_node_id;
+    // Joining members with the highest ids, so that the running node remains the leader
+    let joining_member = |unique_id: u64| {
+        let id = NodeId {
+            unique_id,
+            generation: 0,
+        };
+        member(this_node_id, id, &[], NodeStatus::Joining, 2)
+    };
+    let mut ready_member = joining_member(u64::MAX);
+    ready_member.set_application_state("ready".to_string(), "true".to_string());
+    let unready_member = joining_member(u64::MAX - 1);
+    for member in [ready_member.clone(), unready_member.clone()] {
+        cluster
+            .failure_detector
+            .record_heartbeat(member.id, 0, Instant::now());
+        cluster.cluster_view.merge_member_view(this_node_id, member);
+    }
+    let all_members: HashSet<NodeId> = cluster.cluster_view.known_members.keys().cloned().collect();
+    for member in cluster.cluster_view.known_members.values_mut() {
+        member.state.as_mut().unwrap().observed_by = all_members.clone();
+    }
+
+    assert!(cluster.perform_leader_actions());
+    assert_eq!(member_status(&cluster, this_node_id), Some(NodeStatus::Up));
+    assert_eq!(
+        member_status(&cluster, ready_member.id),
+        Some(NodeStatus::Up)
+    );
+    assert_eq!(
+        member_status(&cluster, unready_member.id),
+        Some(NodeStatus::Down)
+    );
+    assert_eq!(
+        cluster.admission_denial(unready_member.id),
+        Some("not ready")
+    );
+}
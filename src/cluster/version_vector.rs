@@ -33,16 +33,14 @@ impl VersionVector {
 /// Implementation-wise: the comparison function goes trough every node/version pair
 /// in the LHS vector and comapres it with its RHS counterpart. If the RHS version for a node is strictly inferior to
 /// its LHS counterpart, or if it is absent altogether from the RHS, we mark it as "behind" the LHS
-pub(crate) struct VersionVectorOffset<'lhs, 'rhs> {
-    lhs: &'lhs VersionVector,
-    rhs: &'rhs VersionVector,
+pub(crate) struct VersionVectorOffset {
     /// A set of nodes whose latest version has not yet been observed by the RHS
     /// A non-empty set indicates that the RHS version vector is lagging behind.
     pub(crate) behind_lhs: HashSet<NodeId>,
 }
 
-impl<'lhs, 'rhs> VersionVectorOffset<'lhs, 'rhs> {
-    pub(crate) fn of(lhs: &'lhs VersionVector, rhs: &'rhs VersionVector) -> Self {
+impl VersionVectorOffset {
+    pub(crate) fn of(lhs: &VersionVector, rhs: &VersionVector) -> Self {
         let mut behind_lhs = HashSet::new();
         for (node_id, lhs_version) in &lhs.versions {
             match rhs.versions.get(node_id) {
@@ -55,11 +53,7 @@ impl<'lhs, 'rhs> VersionVectorOffset<'lhs, 'rhs> {
                 _ => (),
             }
         }
-        Self {
-            lhs,
-            rhs,
-            behind_lhs,
-        }
+        Self { behind_lhs }
     }
 }
 
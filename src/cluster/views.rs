@@ -1,4 +1,4 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 #[cfg(test)]
 use quickcheck::Arbitrary;
@@ -6,8 +6,14 @@ use quickcheck::Arbitrary;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-use super::version_vector::VersionVector;
-use crate::node::{NodeId, NodeStatus};
+use super::{
+    reachability::{Reachability, ReachabilityRecord},
+    version_vector::VersionVector,
+};
+use crate::{
+    node::{NodeId, NodeStatus},
+    protocol::Protocol,
+};
 
 /// A view of how the running node views the cluster, that is how it views itself and its peers.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -16,15 +22,31 @@ pub struct ClusterView {
     pub known_members: HashMap<NodeId, MemberView>,
     pub(crate) version_vector: VersionVector,
     pub(crate) heartbeats: HashMap<NodeId, u64>,
+    pub(crate) reachability: Reachability,
+    /// The members that have been [removed](super::removal) from the cluster, along with the time of their removal
+    /// in milliseconds since the UNIX epoch
+    pub(crate) tombstones: HashMap<NodeId, u64>,
+    /// The members that have been [downed](NodeStatus::Down). A quarantined member stays down whatever the views
+    /// of it the running node merges later on, so that its stale gossip can't bring it back.
+    /// A restarted node gets a new [NodeId], and is not affected by the quarantine of its previous incarnation.
+    pub(crate) quarantine: HashSet<NodeId>,
 }
 
 impl ClusterView {
-    pub(crate) fn initial(this_node_id: NodeId, this_node_advertised_url: Url) -> Self {
+    pub(crate) fn initial(
+        this_node_id: NodeId,
+        this_node_advertised_url: Url,
+        this_node_roles: BTreeSet<String>,
+    ) -> Self {
         let mut known_members = HashMap::new();
         let mut heartbeats = HashMap::new();
         let mut version_vector = VersionVector::default();
 
-        let this_node = MemberView::this_node_initial_view(this_node_id, this_node_advertised_url);
+        let this_node = MemberView::this_node_initial_view(
+            this_node_id,
+            this_node_advertised_url,
+            this_node_roles,
+        );
         version_vector.versions.insert(
             this_node_id,
             this_node.state.as_ref().map_or(0, |s| s.version),
@@ -39,12 +61,20 @@ impl ClusterView {
             known_members,
             version_vector,
             heartbeats,
+            reachability: Reachability::default(),
+            tombstones: HashMap::new(),
+            quarantine: HashSet::new(),
         }
     }
 
     /// Merges a received view from another node into this view.
     /// This function makes [ClusterView] a Convergent Replicated Data Type (CvRDT)
+    /// Views of removed members are ignored, and quarantined members remain down.
     pub(crate) fn merge_member_view(&mut self, this_node_id: NodeId, other_node: MemberView) {
+        if self.tombstones.contains_key(&other_node.id) {
+            return;
+        }
+        let node_id = other_node.id;
         let merged_member_view = self
             .known_members
             .entry(other_node.id)
@@ -58,6 +88,94 @@ impl ClusterView {
             self.version_vector
                 .record_version(merged_member_view.id, state.version);
         }
+        self.enforce_quarantine(this_node_id, node_id);
+    }
+
+    /// Merges the quarantine received from another node. It must be merged after the member views it came with:
+    /// only the members this view already knows to be down or removed are quarantined, so that a peer can't down
+    /// a member through its quarantine alone.
+    pub(crate) fn merge_quarantine(&mut self, incoming: HashSet<NodeId>) {
+        let known_down: Vec<NodeId> = incoming
+            .into_iter()
+            .filter(|node_id| {
+                self.tombstones.contains_key(node_id)
+                    || self
+                        .known_members
+                        .get(node_id)
+                        .and_then(|m| m.state.as_ref())
+                        .is_some_and(|s| s.node_status >= NodeStatus::Down)
+            })
+            .collect();
+        self.quarantine.extend(known_down);
+    }
+
+    /// Keeps a quarantined member down, whatever the version of the view that has tried to bring it back.
+    /// The member is downed anew, with a version higher than the one of that view, so that it stays down on the
+    /// nodes the view came from too.
+    fn enforce_quarantine(&mut self, this_node_id: NodeId, node_id: NodeId) {
+        if !self.quarantine.contains(&node_id) {
+            return;
+        }
+        let brought_back = self
+            .known_members
+            .get(&node_id)
+            .and_then(|m| m.state.as_ref())
+            .is_some_and(|s| s.node_status < NodeStatus::Down);
+        if brought_back {
+            self.update_member_status(this_node_id, node_id, NodeStatus::Down);
+        }
+    }
+
+    /// Applies a new status to a known member and bumps its version, so that the new status wins over
+    /// older views when other nodes merge it. The running node becomes the only observer of the new state.
+    /// Downed members are quarantined. Returns false if the member is unknown.
+    pub(crate) fn update_member_status(
+        &mut self,
+        this_node_id: NodeId,
+        node_id: NodeId,
+        node_status: NodeStatus,
+    ) -> bool {
+        match self
+            .known_members
+            .get_mut(&node_id)
+            .and_then(|m| m.state.as_mut())
+        {
+            Some(state) => {
+                if node_status == NodeStatus::Down {
+                    self.quarantine.insert(node_id);
+                }
+                state.node_status = node_status;
+                state.version = state.version.saturating_add(1);
+                state.observed_by = HashSet::from([this_node_id]);
+                self.version_vector.record_version(node_id, state.version);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Merges reachability records received from another node, except those about removed members
+    pub(crate) fn merge_reachability(&mut self, incoming: Vec<ReachabilityRecord>) {
+        let tombstones = &self.tombstones;
+        self.reachability.merge(incoming.into_iter().filter(|r| {
+            !tombstones.contains_key(&r.observer) && !tombstones.contains_key(&r.subject)
+        }));
+    }
+
+    /// Forgets everything about a member: its view, its heartbeat, its version,
+    /// the reachability records it is part of, and the observations it has made of other members
+    pub(crate) fn prune(&mut self, node_id: NodeId) {
+        self.known_members.remove(&node_id);
+        self.heartbeats.remove(&node_id);
+        self.version_vector.versions.remove(&node_id);
+        self.reachability.remove_member(node_id);
+        for state in self
+            .known_members
+            .values_mut()
+            .filter_map(|m| m.state.as_mut())
+        {
+            state.observed_by.remove(&node_id);
+        }
     }
 
     #[cfg(test)]
@@ -80,6 +198,9 @@ impl ClusterView {
             known_members: members,
             version_vector,
             heartbeats,
+            reachability: Reachability::default(),
+            tombstones: HashMap::new(),
+            quarantine: HashSet::new(),
         }
     }
 }
@@ -88,6 +209,21 @@ impl ClusterView {
 pub(crate) struct PartialClusterView {
     pub(crate) this_node_id: NodeId,
     pub(crate) members: HashMap<NodeId, MemberView>,
+    pub(crate) reachability: Vec<ReachabilityRecord>,
+    pub(crate) tombstones: HashMap<NodeId, u64>,
+    pub(crate) quarantine: HashSet<NodeId>,
+    /// The token the sender presents to be [admitted](super::admission) into the cluster.
+    /// Only set in the views the sender pushes itself, so that join tokens are never gossiped.
+    pub(crate) join_token: Option<String>,
+}
+
+impl PartialClusterView {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.members.is_empty()
+            && self.reachability.is_empty()
+            && self.tombstones.is_empty()
+            && self.quarantine.is_empty()
+    }
 }
 
 /// A view of how the running node views one of its peers
@@ -96,14 +232,37 @@ pub(crate) struct PartialClusterView {
 pub struct MemberView {
     pub id: NodeId,
     pub advertised_addr: Url,
+    /// The roles the node has declared when it joined the cluster.
+    /// Roles never change for a given [NodeId]: a node must be restarted to change its roles.
+    pub roles: BTreeSet<String>,
+    /// Small key/value pairs published by the node itself (its load, its version, whether it is ready to serve requests ...),
+    /// and gossiped along with its membership. Only the node itself is allowed to update its application state.
+    pub application_state: BTreeMap<String, VersionedValue>,
+    /// The version of the gossip protocol the node speaks, and the features it supports.
+    /// Like roles, the protocol never changes for a given [NodeId].
+    pub protocol: Protocol,
     pub state: Option<MemberViewState>,
 }
 
+/// A value of the [application state](MemberView::application_state) of a node.
+/// When reconciling two views of the same entry, the highest version always wins.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VersionedValue {
+    /// Versions are taken from a counter that is local to each node and increases every time
+    /// one of its entries is updated, so they are unique across all the entries of a node.
+    pub version: u64,
+    pub value: String,
+}
+
 impl MemberView {
-    fn this_node_initial_view(id: NodeId, advertised_url: Url) -> Self {
+    fn this_node_initial_view(id: NodeId, advertised_url: Url, roles: BTreeSet<String>) -> Self {
         Self {
             id,
             advertised_addr: advertised_url,
+            roles,
+            application_state: BTreeMap::new(),
+            protocol: Protocol::current(),
             state: Some(MemberViewState {
                 node_status: NodeStatus::Joining,
                 heartbeat: 0,
@@ -113,19 +272,77 @@ impl MemberView {
                     set.insert(id);
                     set
                 },
+                incarnation: 0,
+                suspected: false,
             }),
         }
     }
 
+    /// Tells whether the node has declared the given role
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.contains(role)
+    }
+
+    /// Returns the value the node has published for the given key of its application state
+    pub fn application_state_value(&self, key: &str) -> Option<&str> {
+        self.application_state.get(key).map(|v| v.value.as_str())
+    }
+
+    /// Returns the highest version among the entries of the application state of the node
+    pub(crate) fn application_state_version(&self) -> u64 {
+        self.application_state
+            .values()
+            .map(|v| v.version)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Sets an entry of the application state, with a version greater than any other entry's.
+    /// Returns false if the entry already had this value.
+    pub(crate) fn set_application_state(&mut self, key: String, value: String) -> bool {
+        if self.application_state_value(&key) == Some(value.as_str()) {
+            return false;
+        }
+        let version = self.application_state_version() + 1;
+        self.application_state
+            .insert(key, VersionedValue { version, value });
+        true
+    }
+
     fn merge(&mut self, incoming: MemberView) {
         debug_assert_eq!(
             self.id, incoming.id,
             "Tried to merge unrelated member views"
         );
 
-        if incoming.id.generation > self.id.generation {
+        if incoming.id.incarnation() > self.id.incarnation() {
             *self = incoming
-        } else if incoming.id.generation == self.id.generation {
+        } else if incoming.id.incarnation() == self.id.incarnation() {
+            // Roles are immutable for a given node id, so both views should carry the same roles.
+            // If they don't, only the roles both declare are kept: the merge stays commutative, and a peer can't grant
+            // a known member roles it didn't declare when it joined.
+            if self.roles != incoming.roles {
+                log::warn!(
+                    "Conflicting roles for member {}: {:?} and {:?}",
+                    self.id,
+                    self.roles,
+                    incoming.roles
+                );
+                self.roles.retain(|role| incoming.roles.contains(role));
+            }
+            self.protocol = std::cmp::max(self.protocol.clone(), incoming.protocol);
+            for (key, incoming_value) in incoming.application_state {
+                self.application_state
+                    .entry(key)
+                    .and_modify(|value| {
+                        *value = std::cmp::max(value.clone(), incoming_value.clone())
+                    })
+                    .or_insert(incoming_value);
+            }
+            let incoming_liveness = incoming
+                .state
+                .as_ref()
+                .map(|s| (s.incarnation, s.suspected));
             match (&mut self.state, incoming.state) {
                 // Whenever the incoming view carries data and ours doesn't, discard our view
                 (None, Some(s)) => self.state = Some(s),
@@ -150,15 +367,16 @@ impl MemberView {
                 }
 
                 // If our both view have the same version number but conflicting statuses, resolve the conflict
-                // by applying status priority rules
+                // by applying status priority rules: the highest-priority status wins, along with its observers
                 (Some(self_state), Some(incoming_state))
                     if self_state.node_status != incoming_state.node_status =>
                 {
-                    self_state.version += 1;
                     self_state.heartbeat =
                         std::cmp::max(self_state.heartbeat, incoming_state.heartbeat);
-                    self_state.node_status =
-                        std::cmp::max(self_state.node_status, incoming_state.node_status)
+                    if incoming_state.node_status > self_state.node_status {
+                        self_state.node_status = incoming_state.node_status;
+                        self_state.observed_by = incoming_state.observed_by;
+                    }
                 }
 
                 // If our both view have the same version number and no conflict, just merge the observer sets
@@ -169,6 +387,11 @@ impl MemberView {
                 }
                 _ => (),
             }
+            if let (Some(state), Some((incarnation, suspected))) =
+                (&mut self.state, incoming_liveness)
+            {
+                state.merge_liveness(incarnation, suspected);
+            }
         };
     }
 }
@@ -186,13 +409,27 @@ pub struct MemberViewState {
     /// - [NodeStatus::Up] precedes [NodeStatus::Joining]
     /// - [NodeStatus::Leaving] precedes [NodeStatus::Up] and [NodeStatus::Joining]
     /// - [NodeStatus::Exiting] precedes [NodeStatus::Leaving], [NodeStatus::Up] and [NodeStatus::Joining]
-    /// - [NodeStatus::Down] precedes all other statuses
+    /// - [NodeStatus::Down] precedes [NodeStatus::Exiting], [NodeStatus::Leaving], [NodeStatus::Up] and [NodeStatus::Joining]
+    /// - [NodeStatus::Removed] precedes all other statuses
     pub version: u16,
     /// The heartbeat is a monotonically increasing counter used to detect failure.
     /// Nodes will regularly increment their own counter and gossip the latest value to their peers.
     /// Nodes are not allowed to update other nodes' heartbeats counter.
     pub heartbeat: u64,
     pub observed_by: HashSet<NodeId>,
+    /// Only the node itself increases its incarnation, to refute a suspicion about its liveness.
+    /// When reconciling two views, the highest incarnation wins, and for the same incarnation a suspicion wins.
+    pub incarnation: u64,
+    /// Tells whether another member has [suspected](crate::cluster::swim) the node of having failed,
+    /// at the current incarnation of the node.
+    pub suspected: bool,
+}
+
+impl MemberViewState {
+    pub(crate) fn merge_liveness(&mut self, incarnation: u64, suspected: bool) {
+        (self.incarnation, self.suspected) =
+            std::cmp::max((self.incarnation, self.suspected), (incarnation, suspected));
+    }
 }
 
 #[cfg(test)]
@@ -322,6 +559,11 @@ mod tests {
             Self {
                 this_node_id: NodeId::arbitrary(g),
                 members,
+                reachability: Vec::new(),
+                tombstones: HashMap::new(),
+                quarantine: HashSet::new(),
+                join_token: Option::<bool>::arbitrary(g)
+                    .map(|valid| if valid { "valid" } else { "invalid" }.to_string()),
             }
         }
     }
@@ -331,6 +573,35 @@ mod tests {
             Self {
                 id: NodeId::arbitrary(g),
                 advertised_addr: Url::from_str("test:8080").unwrap(),
+                roles: Vec::<bool>::arbitrary(g)
+                    .into_iter()
+                    .zip(["frontend", "worker", "storage"])
+                    .filter(|(has_role, _)| *has_role)
+                    .map(|(_, role)| role.to_string())
+                    .collect(),
+                application_state: Vec::<(bool, u8, u8)>::arbitrary(g)
+                    .into_iter()
+                    .zip(["load", "shards", "ready"])
+                    .filter(|((is_set, _, _), _)| *is_set)
+                    .map(|((_, version, value), key)| {
+                        (
+                            key.to_string(),
+                            VersionedValue {
+                                version: version as u64,
+                                value: value.to_string(),
+                            },
+                        )
+                    })
+                    .collect(),
+                protocol: Protocol {
+                    version: u8::arbitrary(g) as u32,
+                    features: Vec::<bool>::arbitrary(g)
+                        .into_iter()
+                        .zip(["swim", "tombstones"])
+                        .filter(|(supported, _)| *supported)
+                        .map(|(_, feature)| feature.to_string())
+                        .collect(),
+                },
                 state: Option::<MemberViewState>::arbitrary(g),
             }
         }
@@ -343,6 +614,8 @@ mod tests {
                 version: u16::arbitrary(g),
                 heartbeat: u64::arbitrary(g),
                 observed_by: HashSet::<NodeId>::arbitrary(g),
+                incarnation: u8::arbitrary(g) as u64,
+                suspected: bool::arbitrary(g),
             }
         }
     }
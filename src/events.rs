@@ -0,0 +1,665 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use tokio::{
+    select,
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc,
+    },
+};
+use url::Url;
+
+use crate::{
+    cluster::Cluster,
+    node::{NodeId, NodeStatus},
+};
+
+/// Describes what a new subscriber receives before the events happening after its subscription
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SubscriptionMode {
+    /// The subscriber only receives events happening after the subscription
+    #[default]
+    NewEventsOnly,
+    /// The subscriber first receives the current membership of the cluster as synthetic events
+    /// ([ClusterEvent::MemberUp] for members that are up, [ClusterEvent::Unreachable] for unreachable members ...),
+    /// then the events happening after the subscription.
+    InitialStateAsEvents,
+}
+
+/// A member of the cluster, as carried by [ClusterEvent]s
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Member {
+    pub id: NodeId,
+    pub advertised_addr: Url,
+    pub roles: BTreeSet<String>,
+    pub status: NodeStatus,
+}
+
+impl Member {
+    /// Tells whether the member has declared the given role
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.contains(role)
+    }
+}
+
+/// An event happening in the cluster, as observed by the running node.
+///
+/// Events are computed by comparing the state of the cluster before and after every change,
+/// so they are only produced when something has actually changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ClusterEvent {
+    /// A new member has been discovered, and is joining the cluster
+    MemberJoined(Member),
+    /// A member has been moved to [NodeStatus::Up] by the leader
+    MemberUp(Member),
+    /// A member has started to leave the cluster gracefully
+    MemberLeft(Member),
+    /// A leaving member has been moved to [NodeStatus::Exiting] by the leader
+    MemberExited(Member),
+    /// A member has been marked as [NodeStatus::Down]
+    MemberDowned(Member),
+    /// A member has been removed from the cluster. The event carries the last known state of the member.
+    MemberRemoved(Member),
+    /// A node has restarted while its previous incarnation was still a member: a new member has joined with the same
+    /// URL and a newer [generation](NodeId::generation). The previous incarnation is downed, then removed.
+    MemberRestarted { previous_id: NodeId, member: Member },
+    /// The running node can no longer reach a member
+    Unreachable(Member),
+    /// The running node can reach a previously unreachable member again
+    Reachable(Member),
+    /// A member has published a new value for an entry of its application state
+    ApplicationStateChanged {
+        node_id: NodeId,
+        key: String,
+        value: String,
+    },
+    /// The leader of the cluster has changed
+    LeaderChanged(Option<NodeId>),
+    /// The cluster has converged, i.e. every member has observed the latest state of the cluster
+    Converged,
+    /// A member has denied the running node admission into the cluster, see [admission](crate::cluster::admission).
+    /// The event is sent again if the member gives another reason later on.
+    AdmissionDenied { by: Url, reason: String },
+    /// A node configured with another [cluster name](crate::CouncilBuilder::with_cluster_name) has contacted
+    /// the running node, and has been turned away. This usually reveals a misconfigured peer list.
+    ForeignClusterContact {
+        cluster_name: String,
+        remote_addr: Option<SocketAddr>,
+    },
+}
+
+impl ClusterEvent {
+    fn for_member_status(member: Member) -> Self {
+        match member.status {
+            NodeStatus::Joining => ClusterEvent::MemberJoined(member),
+            NodeStatus::Up => ClusterEvent::MemberUp(member),
+            NodeStatus::Leaving => ClusterEvent::MemberLeft(member),
+            NodeStatus::Exiting => ClusterEvent::MemberExited(member),
+            NodeStatus::Down => ClusterEvent::MemberDowned(member),
+            NodeStatus::Removed => ClusterEvent::MemberRemoved(member),
+        }
+    }
+}
+
+/// The part of the state of the cluster that [ClusterEvent]s describe.
+/// Comparing two successive snapshots yields the events that happened in between.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct MembershipSnapshot {
+    members: HashMap<NodeId, Member>,
+    unreachable: HashSet<NodeId>,
+    application_state: HashMap<NodeId, BTreeMap<String, String>>,
+    leader: Option<NodeId>,
+    converged: bool,
+}
+
+impl MembershipSnapshot {
+    pub(crate) fn of(cluster: &Cluster) -> Self {
+        let members: HashMap<NodeId, Member> = cluster
+            .cluster_view
+            .known_members
+            .values()
+            .filter_map(|m| {
+                // Removed members are about to be pruned, and are reported as such
+                let state = m
+                    .state
+                    .as_ref()
+                    .filter(|s| s.node_status != NodeStatus::Removed)?;
+                Some((
+                    m.id,
+                    Member {
+                        id: m.id,
+                        advertised_addr: m.advertised_addr.clone(),
+                        roles: m.roles.clone(),
+                        status: state.node_status,
+                    },
+                ))
+            })
+            .collect();
+        let unreachable = cluster.unreachable_since.keys().cloned().collect();
+        let application_state = cluster
+            .cluster_view
+            .known_members
+            .values()
+            .filter(|m| members.contains_key(&m.id) && !m.application_state.is_empty())
+            .map(|m| {
+                (
+                    m.id,
+                    m.application_state
+                        .iter()
+                        .map(|(key, v)| (key.clone(), v.value.clone()))
+                        .collect(),
+                )
+            })
+            .collect();
+
+        Self {
+            members,
+            unreachable,
+            application_state,
+            leader: cluster.leader(),
+            converged: cluster.has_converged(),
+        }
+    }
+
+    /// Updates this snapshot with an event, so that it reflects the state of the cluster right after the event
+    fn apply(&mut self, event: &ClusterEvent) {
+        match event {
+            ClusterEvent::MemberJoined(member)
+            | ClusterEvent::MemberUp(member)
+            | ClusterEvent::MemberLeft(member)
+            | ClusterEvent::MemberExited(member)
+            | ClusterEvent::MemberDowned(member) => {
+                self.members.insert(member.id, member.clone());
+            }
+            ClusterEvent::MemberRemoved(member) => {
+                self.members.remove(&member.id);
+                self.unreachable.remove(&member.id);
+                self.application_state.remove(&member.id);
+            }
+            ClusterEvent::Unreachable(member) => {
+                self.unreachable.insert(member.id);
+            }
+            ClusterEvent::Reachable(member) => {
+                self.unreachable.remove(&member.id);
+            }
+            ClusterEvent::ApplicationStateChanged {
+                node_id,
+                key,
+                value,
+            } => {
+                self.application_state
+                    .entry(*node_id)
+                    .or_default()
+                    .insert(key.clone(), value.clone());
+            }
+            ClusterEvent::LeaderChanged(leader) => self.leader = *leader,
+            ClusterEvent::Converged => self.converged = true,
+            ClusterEvent::MemberRestarted { .. }
+            | ClusterEvent::AdmissionDenied { .. }
+            | ClusterEvent::ForeignClusterContact { .. } => {}
+        }
+    }
+
+    /// Returns the latest incarnation of a node this snapshot knows, if the given member is a newer one
+    fn previous_incarnation_of(&self, node_id: NodeId) -> Option<NodeId> {
+        if self.members.contains_key(&node_id) {
+            return None;
+        }
+        self.members
+            .keys()
+            .filter(|id| {
+                id.unique_id == node_id.unique_id && id.incarnation() < node_id.incarnation()
+            })
+            .max_by_key(|id| id.incarnation())
+            .copied()
+    }
+
+    /// Returns the events that lead from this snapshot to the `next` one
+    pub(crate) fn diff(&self, next: &Self) -> Vec<ClusterEvent> {
+        let mut events = Vec::new();
+
+        let mut next_members: Vec<&Member> = next.members.values().collect();
+        next_members.sort_by_key(|m| m.id);
+        for member in next_members {
+            let status_changed = self
+                .members
+                .get(&member.id)
+                .is_none_or(|previous| previous.status != member.status);
+            if status_changed {
+                events.push(ClusterEvent::for_member_status(member.clone()));
+            }
+            if let Some(previous_id) = self.previous_incarnation_of(member.id) {
+                events.push(ClusterEvent::MemberRestarted {
+                    previous_id,
+                    member: member.clone(),
+                });
+            }
+        }
+
+        let mut removed_members: Vec<&Member> = self
+            .members
+            .values()
+            .filter(|m| !next.members.contains_key(&m.id))
+            .collect();
+        removed_members.sort_by_key(|m| m.id);
+        events.extend(
+            removed_members
+                .into_iter()
+                .map(|m| ClusterEvent::MemberRemoved(m.clone())),
+        );
+
+        let mut application_states: Vec<_> = next.application_state.iter().collect();
+        application_states.sort_by_key(|(id, _)| **id);
+        for (node_id, entries) in application_states {
+            let previous_entries = self.application_state.get(node_id);
+            for (key, value) in entries {
+                if previous_entries.and_then(|e| e.get(key)) != Some(value) {
+                    events.push(ClusterEvent::ApplicationStateChanged {
+                        node_id: *node_id,
+                        key: key.clone(),
+                        value: value.clone(),
+                    });
+                }
+            }
+        }
+
+        let mut reachability_changes: Vec<(NodeId, bool)> = next
+            .unreachable
+            .difference(&self.unreachable)
+            .map(|id| (*id, false))
+            .chain(
+                self.unreachable
+                    .difference(&next.unreachable)
+                    .map(|id| (*id, true)),
+            )
+            .collect();
+        reachability_changes.sort();
+        for (id, reachable) in reachability_changes {
+            if let Some(member) = next.members.get(&id) {
+                events.push(if reachable {
+                    ClusterEvent::Reachable(member.clone())
+                } else {
+                    ClusterEvent::Unreachable(member.clone())
+                });
+            }
+        }
+
+        if self.leader != next.leader {
+            events.push(ClusterEvent::LeaderChanged(next.leader));
+        }
+
+        if !self.converged && next.converged {
+            events.push(ClusterEvent::Converged);
+        }
+
+        events
+    }
+}
+
+/// Publishes [ClusterEvent]s to subscribers, along with the membership they lead to.
+///
+/// Events are sent and the membership is updated under the same lock, so that a new subscriber registers
+/// synchronously, without going through the main loop, and gets a membership consistent with the events it
+/// receives next.
+#[derive(Debug, Clone)]
+pub(crate) struct EventPublisher {
+    publication: Arc<Mutex<Publication>>,
+}
+
+#[derive(Debug)]
+struct Publication {
+    /// Dropped once the main loop has stopped, so that subscriptions end
+    sender: Option<broadcast::Sender<ClusterEvent>>,
+    membership: MembershipSnapshot,
+}
+
+impl EventPublisher {
+    pub(crate) fn new(membership: MembershipSnapshot, capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self {
+            publication: Arc::new(Mutex::new(Publication {
+                sender: Some(sender),
+                membership,
+            })),
+        }
+    }
+
+    /// Registers a new subscriber, and returns the membership the events it receives apply to.
+    /// Returns `None` once the main loop has stopped.
+    pub(crate) fn subscribe(
+        &self,
+    ) -> Option<(broadcast::Receiver<ClusterEvent>, MembershipSnapshot)> {
+        let publication = self.lock();
+        let receiver = publication.sender.as_ref()?.subscribe();
+        Some((receiver, publication.membership.clone()))
+    }
+
+    /// Sends the events that lead from the last published membership to the given one.
+    /// Returns false if the membership hasn't changed.
+    pub(crate) fn publish_membership(
+        &self,
+        this_node_id: NodeId,
+        membership: MembershipSnapshot,
+    ) -> bool {
+        let mut publication = self.lock();
+        if membership == publication.membership {
+            return false;
+        }
+        for event in publication.membership.diff(&membership) {
+            log::debug!("[Node id: {}] {:?}", this_node_id, event);
+            if let Some(sender) = &publication.sender {
+                let _ = sender.send(event);
+            }
+        }
+        publication.membership = membership;
+        true
+    }
+
+    /// Sends an event that doesn't change the membership
+    pub(crate) fn publish(&self, event: ClusterEvent) {
+        if let Some(sender) = &self.lock().sender {
+            let _ = sender.send(event);
+        }
+    }
+
+    /// Ends every subscription, once the main loop has stopped
+    pub(crate) fn close(&self) {
+        self.lock().sender = None;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Publication> {
+        self.publication
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Forwards the events received by a subscription to `subscriber`, until the subscriber is dropped or the main loop
+/// stops.
+///
+/// The subscriber keeps track of the state of the cluster by applying every event it forwards to a snapshot.
+/// When it lags behind the publisher and misses events, it subscribes again to get a fresh snapshot, and forwards
+/// the events that lead from its own snapshot to the fresh one.
+pub(crate) async fn forward_events(
+    publisher: EventPublisher,
+    (mut receiver, mut membership): (broadcast::Receiver<ClusterEvent>, MembershipSnapshot),
+    mode: SubscriptionMode,
+    subscriber: mpsc::Sender<ClusterEvent>,
+) {
+    let initial_events = match mode {
+        SubscriptionMode::NewEventsOnly => Vec::new(),
+        SubscriptionMode::InitialStateAsEvents => MembershipSnapshot::default().diff(&membership),
+    };
+    for event in initial_events {
+        if subscriber.send(event).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        let received = select! {
+            received = receiver.recv() => received,
+            _ = subscriber.closed() => return,
+        };
+        let events = match received {
+            Ok(event) => {
+                membership.apply(&event);
+                vec![event]
+            }
+            Err(RecvError::Lagged(missed_events)) => {
+                log::debug!(
+                    "Event subscriber missed {} events, resynchronizing",
+                    missed_events
+                );
+                let (new_receiver, fresh_membership) = match publisher.subscribe() {
+                    Some(subscription) => subscription,
+                    None => return,
+                };
+                let events = membership.diff(&fresh_membership);
+                receiver = new_receiver;
+                membership = fresh_membership;
+                events
+            }
+            Err(RecvError::Closed) => return,
+        };
+        for event in events {
+            if subscriber.send(event).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(unique_id: u64, status: NodeStatus) -> Member {
+        Member {
+            id: NodeId {
+                unique_id,
+                generation: 1,
+            },
+            advertised_addr: Url::parse(&format!("http://localhost:{}", 8000 + unique_id)).unwrap(),
+            roles: BTreeSet::new(),
+            status,
+        }
+    }
+
+    fn snapshot(members: &[Member]) -> MembershipSnapshot {
+        MembershipSnapshot {
+            members: members.iter().map(|m| (m.id, m.clone())).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn identical_snapshots_produce_no_event() {
+        let snapshot = snapshot(&[member(1, NodeStatus::Up), member(2, NodeStatus::Joining)]);
+        assert_eq!(snapshot.diff(&snapshot.clone()), vec![]);
+    }
+
+    #[test]
+    fn status_changes_produce_member_events() {
+        let before = snapshot(&[
+            member(1, NodeStatus::Joining),
+            member(2, NodeStatus::Up),
+            member(3, NodeStatus::Leaving),
+            member(4, NodeStatus::Up),
+            member(5, NodeStatus::Exiting),
+        ]);
+        let after = snapshot(&[
+            member(1, NodeStatus::Up),
+            member(2, NodeStatus::Leaving),
+            member(3, NodeStatus::Exiting),
+            member(4, NodeStatus::Down),
+            member(6, NodeStatus::Joining),
+        ]);
+
+        assert_eq!(
+            before.diff(&after),
+            vec![
+                ClusterEvent::MemberUp(member(1, NodeStatus::Up)),
+                ClusterEvent::MemberLeft(member(2, NodeStatus::Leaving)),
+                ClusterEvent::MemberExited(member(3, NodeStatus::Exiting)),
+                ClusterEvent::MemberDowned(member(4, NodeStatus::Down)),
+                ClusterEvent::MemberJoined(member(6, NodeStatus::Joining)),
+                ClusterEvent::MemberRemoved(member(5, NodeStatus::Exiting)),
+            ]
+        );
+    }
+
+    #[test]
+    fn reachability_leader_and_convergence_changes_produce_events() {
+        let a = member(1, NodeStatus::Up);
+        let b = member(2, NodeStatus::Up);
+        let before = MembershipSnapshot {
+            unreachable: HashSet::from([a.id]),
+            leader: Some(b.id),
+            converged: false,
+            ..snapshot(&[a.clone(), b.clone()])
+        };
+        let after = MembershipSnapshot {
+            unreachable: HashSet::from([b.id]),
+            leader: Some(a.id),
+            converged: true,
+            ..snapshot(&[a.clone(), b.clone()])
+        };
+
+        assert_eq!(
+            before.diff(&after),
+            vec![
+                ClusterEvent::Reachable(a.clone()),
+                ClusterEvent::Unreachable(b),
+                ClusterEvent::LeaderChanged(Some(a.id)),
+                ClusterEvent::Converged,
+            ]
+        );
+        // Losing convergence isn't an event
+        assert!(!after
+            .diff(&before)
+            .into_iter()
+            .any(|e| e == ClusterEvent::Converged));
+    }
+
+    #[test]
+    fn application_state_changes_produce_events() {
+        let a = member(1, NodeStatus::Up);
+        let before = MembershipSnapshot {
+            application_state: HashMap::from([(
+                a.id,
+                BTreeMap::from([
+                    ("load".to_string(), "0.5".to_string()),
+                    ("ready".to_string(), "true".to_string()),
+                ]),
+            )]),
+            ..snapshot(std::slice::from_ref(&a))
+        };
+        let after = MembershipSnapshot {
+            application_state: HashMap::from([(
+                a.id,
+                BTreeMap::from([
+                    ("load".to_string(), "0.7".to_string()),
+                    ("ready".to_string(), "true".to_string()),
+                ]),
+            )]),
+            ..snapshot(std::slice::from_ref(&a))
+        };
+
+        assert_eq!(
+            before.diff(&after),
+            vec![ClusterEvent::ApplicationStateChanged {
+                node_id: a.id,
+                key: "load".to_string(),
+                value: "0.7".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn new_incarnations_of_known_members_produce_restart_events() {
+        let previous = member(1, NodeStatus::Up);
+        let restarted = Member {
+            id: NodeId {
+                generation: 2,
+                ..previous.id
+            },
+            status: NodeStatus::Joining,
+            ..previous.clone()
+        };
+        let downed = Member {
+            status: NodeStatus::Down,
+            ..previous.clone()
+        };
+        let before = snapshot(std::slice::from_ref(&previous));
+        let after = snapshot(&[downed.clone(), restarted.clone()]);
+
+        assert_eq!(
+            before.diff(&after),
+            vec![
+                ClusterEvent::MemberDowned(downed),
+                ClusterEvent::MemberJoined(restarted.clone()),
+                ClusterEvent::MemberRestarted {
+                    previous_id: previous.id,
+                    member: restarted.clone(),
+                },
+            ]
+        );
+        // A node joining after its previous incarnation has been removed hasn't replaced anything
+        assert_eq!(
+            snapshot(&[]).diff(&snapshot(std::slice::from_ref(&restarted))),
+            vec![ClusterEvent::MemberJoined(restarted)]
+        );
+    }
+
+    #[test]
+    fn applying_the_diff_of_two_snapshots_yields_the_second_snapshot() {
+        let a = member(1, NodeStatus::Up);
+        let b = member(2, NodeStatus::Joining);
+        let c = member(3, NodeStatus::Leaving);
+        let before = MembershipSnapshot {
+            unreachable: HashSet::from([c.id]),
+            application_state: HashMap::from([(
+                a.id,
+                BTreeMap::from([("load".to_string(), "0.5".to_string())]),
+            )]),
+            leader: Some(a.id),
+            converged: true,
+            ..snapshot(&[a.clone(), c.clone()])
+        };
+        let after = MembershipSnapshot {
+            unreachable: HashSet::from([a.id]),
+            application_state: HashMap::from([(
+                a.id,
+                BTreeMap::from([("load".to_string(), "0.7".to_string())]),
+            )]),
+            leader: Some(b.id),
+            converged: true,
+            ..snapshot(&[a, member(2, NodeStatus::Up)])
+        };
+
+        for (from, to) in [
+            (&MembershipSnapshot::default(), &before),
+            (&before, &after),
+            (&after, &before),
+        ] {
+            let mut applied = from.clone();
+            for event in from.diff(to) {
+                applied.apply(&event);
+            }
+            assert_eq!(&applied, to);
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribers_receive_every_event_published_after_subscribing() {
+        let a = member(1, NodeStatus::Joining);
+        let publisher = EventPublisher::new(snapshot(std::slice::from_ref(&a)), 10);
+        let (tx, mut rx) = mpsc::channel(10);
+        let subscription = publisher.subscribe().unwrap();
+
+        let up = snapshot(&[member(1, NodeStatus::Up)]);
+        assert!(publisher.publish_membership(a.id, up.clone()));
+        assert!(!publisher.publish_membership(a.id, up));
+        publisher.close();
+        forward_events(
+            publisher.clone(),
+            subscription,
+            SubscriptionMode::NewEventsOnly,
+            tx,
+        )
+        .await;
+
+        assert!(
+            matches!(rx.recv().await, Some(ClusterEvent::MemberUp(member)) if member.id == a.id)
+        );
+        assert!(rx.recv().await.is_none());
+        assert!(publisher.subscribe().is_none());
+    }
+}
@@ -23,6 +23,7 @@ pub struct DefaultTonicChannelFactory {}
 
 impl DefaultTonicChannelFactory {
     /// Builds a cached [TonicChannelFactory] using the default implementation
+    #[allow(clippy::new_ret_no_self)]
     pub fn new() -> impl TonicChannelFactory {
         TonicChannelFactoryCache::new(DefaultTonicChannelFactory {})
     }
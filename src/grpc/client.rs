@@ -1,39 +1,314 @@
-use std::{collections::HashMap, error::Error, sync::Arc};
+use std::{
+    error::Error,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::SystemTime,
+};
 
-use tonic::{transport::Channel, Request};
+use http_body::Body as _;
+use tonic::{body::BoxBody, metadata::MetadataMap, transport::Channel, Request, Status};
+use tower_service::Service;
 use url::Url;
 
-use super::{protos, TonicChannelFactory};
-use crate::{cluster::views::PartialClusterView, node::NodeId};
+use super::{
+    dtos_conversions::{check_header, DecodeError},
+    keyring::{BufferedBody, SignedMessage},
+    protos, Keyring, TonicChannelFactory,
+};
+use crate::{
+    cluster::{
+        admission::AdmissionDenied, digest::ClusterDigest, swim::PingAck, views::PartialClusterView,
+    },
+    node::NodeId,
+};
 
-pub(crate) type HeartbeatMessage = HashMap<NodeId, u64>;
+/// The metadata entry carrying the advertised URL of the sender of a request
+pub(crate) const ADVERTISED_URL_METADATA_KEY: &str = "council-advertised-url";
 
 pub(crate) struct CouncilClient {
     pub(crate) tonic_channel_factory: Arc<dyn TonicChannelFactory + Send + Sync>,
+    /// The name of the cluster of the running node, sent with every request and expected in every response
+    pub(crate) cluster_name: String,
+    /// Signs every request and authenticates every response, when set
+    pub(crate) keyring: Option<Keyring>,
+    /// Sent with every request, so that peers can check it against the certificate of the running node
+    pub(crate) this_node_advertised_url: Url,
+    /// Presented to the [AdmissionHook](crate::cluster::admission::AdmissionHook)s of peers, along with every view the running node pushes
+    pub(crate) join_token: Option<String>,
 }
 
 impl CouncilClient {
+    /// Sends our digest to a peer, which replies with its own digest and the member views we are behind on.
+    /// Fails with [AdmissionDenied] if the peer doesn't admit the running node into the cluster.
+    pub(crate) async fn exchange_digests(
+        &self,
+        node_advertised_url: Url,
+        digest: ClusterDigest,
+    ) -> Result<(ClusterDigest, PartialClusterView), Box<dyn Error + Send + Sync + 'static>> {
+        let mut client = self.get_client_for_url(node_advertised_url.clone()).await?;
+        let request = self.request(protos::ClusterDigest::new(digest, self.header()));
+        let response = client.exchange_digests(request).await?.into_inner();
+        if !response.admission_denied.is_empty() {
+            return Err(Box::new(AdmissionDenied(response.admission_denied)));
+        }
+        self.check_response_header(
+            &node_advertised_url,
+            response.digest.as_ref().and_then(|d| d.header.as_ref()),
+        )?;
+        let digest = decode_response(&node_advertised_url, response.digest, "digest")?;
+        let delta = decode_response(&node_advertised_url, response.delta, "delta")?;
+        Ok((digest, delta))
+    }
+
+    /// Sends the full view of the running node to a peer that doesn't gossip digests, which replies with its own view
     pub(crate) async fn exchange_cluster_views(
         &self,
         node_advertised_url: Url,
-        cluster_view: PartialClusterView,
+        full_view: PartialClusterView,
     ) -> Result<PartialClusterView, Box<dyn Error + Send + Sync + 'static>> {
-        let mut client = self.get_client_for_url(node_advertised_url).await?;
-        let request = Request::new(cluster_view.into());
-        let response = client.exchange_cluster_views(request).await?;
-        Ok(response.into_inner().into())
+        let mut client = self.get_client_for_url(node_advertised_url.clone()).await?;
+        let full_view = PartialClusterView {
+            join_token: self.join_token.clone(),
+            ..full_view
+        };
+        let request = self.request(protos::PartialClusterView::new(full_view, self.header()));
+        let response = client.exchange_cluster_views(request).await?.into_inner();
+        self.check_response_header(&node_advertised_url, response.header.as_ref())?;
+        Ok(decode_response(
+            &node_advertised_url,
+            Some(response),
+            "view",
+        )?)
+    }
+
+    /// Sends a peer the member views it is behind on
+    pub(crate) async fn push_delta(
+        &self,
+        node_advertised_url: Url,
+        delta: PartialClusterView,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let mut client = self.get_client_for_url(node_advertised_url.clone()).await?;
+        let delta = PartialClusterView {
+            join_token: self.join_token.clone(),
+            ..delta
+        };
+        let request = self.request(protos::PartialClusterView::new(delta, self.header()));
+        client.push_delta(request).await?;
+        Ok(())
+    }
+
+    /// Asks a contact point whether it is part of a formed cluster
+    pub(crate) async fn probe(
+        &self,
+        node_advertised_url: Url,
+        this_node_id: NodeId,
+    ) -> Result<bool, Box<dyn Error + Send + Sync + 'static>> {
+        let mut client = self.get_client_for_url(node_advertised_url.clone()).await?;
+        let request = self.request(protos::ProbeRequest {
+            this_node_id: Some(this_node_id.into()),
+            header: Some(self.header()),
+        });
+        let response = client.probe(request).await?.into_inner();
+        self.check_response_header(&node_advertised_url, response.header.as_ref())?;
+        Ok(response.cluster_formed)
+    }
+
+    /// Pings a member directly
+    pub(crate) async fn ping(
+        &self,
+        node_advertised_url: Url,
+        target: NodeId,
+    ) -> Result<PingAck, Box<dyn Error + Send + Sync + 'static>> {
+        let mut client = self.get_client_for_url(node_advertised_url.clone()).await?;
+        let request = self.request(protos::PingRequest {
+            target: Some(target.into()),
+            header: Some(self.header()),
+        });
+        let response = client.ping(request).await?.into_inner();
+        self.check_response_header(&node_advertised_url, response.header.as_ref())?;
+        Ok(decode_response(
+            &node_advertised_url,
+            Some(response),
+            "ack",
+        )?)
+    }
+
+    /// Asks a member to ping another member on behalf of the running node
+    pub(crate) async fn ping_req(
+        &self,
+        helper_advertised_url: Url,
+        target: NodeId,
+        target_advertised_url: Url,
+    ) -> Result<PingAck, Box<dyn Error + Send + Sync + 'static>> {
+        let mut client = self
+            .get_client_for_url(helper_advertised_url.clone())
+            .await?;
+        let request = self.request(protos::IndirectPingRequest {
+            target: Some(target.into()),
+            target_advertised_url: target_advertised_url.to_string(),
+            header: Some(self.header()),
+        });
+        let response = client.ping_req(request).await?.into_inner();
+        self.check_response_header(&helper_advertised_url, response.header.as_ref())?;
+        Ok(decode_response(
+            &helper_advertised_url,
+            Some(response),
+            "ack",
+        )?)
+    }
+
+    /// Sends the heartbeat of the running node to a member monitoring it
+    pub(crate) async fn send_heartbeat(
+        &self,
+        node_advertised_url: Url,
+        this_node_id: NodeId,
+        heartbeat: u64,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let mut client = self.get_client_for_url(node_advertised_url.clone()).await?;
+        let request = self.request(protos::Heartbeat {
+            this_node_id: Some(this_node_id.into()),
+            heartbeat,
+            header: Some(self.header()),
+        });
+        client.send_heartbeat(request).await?;
+        Ok(())
+    }
+
+    fn header(&self) -> protos::ProtocolHeader {
+        protos::ProtocolHeader::new(&self.cluster_name)
+    }
+
+    /// Wraps a message into a request, along with the advertised URL of the running node
+    fn request<M: prost::Message>(&self, message: M) -> Request<M> {
+        let mut metadata = MetadataMap::new();
+        if let Ok(url) = self.this_node_advertised_url.as_str().parse() {
+            metadata.insert(ADVERTISED_URL_METADATA_KEY, url);
+        }
+        let mut request = Request::new(message);
+        *request.metadata_mut() = metadata;
+        request
+    }
+
+    /// Ignores responses sent by nodes of another cluster, or speaking an unsupported protocol version
+    fn check_response_header(
+        &self,
+        node_advertised_url: &Url,
+        header: Option<&protos::ProtocolHeader>,
+    ) -> Result<(), DecodeError> {
+        check_header(header, &self.cluster_name).map_err(|e| {
+            log::warn!("Ignoring a response from {}: {}", node_advertised_url, e);
+            e
+        })
     }
 
     async fn get_client_for_url(
         &self,
         url: Url,
     ) -> Result<
-        protos::gossip_service_client::GossipServiceClient<Channel>,
+        protos::gossip_service_client::GossipServiceClient<AuthenticatedChannel>,
         Box<dyn Error + Send + Sync + 'static>,
     > {
-        let channel = self.tonic_channel_factory.channel_for_url(url).await?;
+        let channel = self
+            .tonic_channel_factory
+            .channel_for_url(url.clone())
+            .await?;
         Ok(protos::gossip_service_client::GossipServiceClient::new(
-            channel,
+            AuthenticatedChannel {
+                channel,
+                keyring: self.keyring.clone(),
+                node_advertised_url: url,
+            },
         ))
     }
 }
+
+/// Wraps the channel to a peer. When a [Keyring] is configured, it signs every request with its primary key,
+/// and ignores responses that aren't signed by any of its keys.
+#[derive(Clone)]
+pub(crate) struct AuthenticatedChannel {
+    channel: Channel,
+    keyring: Option<Keyring>,
+    node_advertised_url: Url,
+}
+
+impl Service<http::Request<BoxBody>> for AuthenticatedChannel {
+    type Response = http::Response<BoxBody>;
+    type Error = Box<dyn Error + Send + Sync + 'static>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.channel.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        // The channel that was polled ready is the one that must send the request
+        let channel = self.channel.clone();
+        let mut channel = std::mem::replace(&mut self.channel, channel);
+        let keyring = self.keyring.clone();
+        let node_advertised_url = self.node_advertised_url.clone();
+        Box::pin(async move {
+            let Some(keyring) = keyring else {
+                let response = channel.call(request).await?;
+                return Ok(response.map(|body| {
+                    body.map_err(|e| Status::from_error(Box::new(e)))
+                        .boxed_unsync()
+                }));
+            };
+            let (mut parts, body) = request.into_parts();
+            let body = BufferedBody::read(body).await?;
+            let path = parts.uri.path().to_string();
+            let request = SignedMessage {
+                path: &path,
+                request_signature: None,
+                body: body.data(),
+            };
+            let request_signature = keyring.sign(&request, &mut parts.headers, SystemTime::now());
+            let body = body.map_err(|never| match never {}).boxed_unsync();
+
+            let response = channel.call(http::Request::from_parts(parts, body)).await?;
+            let (parts, body) = response.into_parts();
+            let body = BufferedBody::read(body).await?;
+            let response = SignedMessage {
+                path: &path,
+                request_signature: Some(&request_signature),
+                body: body.data(),
+            };
+            if let Err(e) = keyring.verify(&response, &parts.headers, SystemTime::now()) {
+                log::warn!(
+                    "Ignoring an unauthenticated response from {}: {}",
+                    node_advertised_url,
+                    e
+                );
+                return Err(Box::new(Status::from(e)) as Self::Error);
+            }
+            Ok(http::Response::from_parts(
+                parts,
+                body.map_err(|never| match never {}).boxed_unsync(),
+            ))
+        })
+    }
+}
+
+/// Decodes a field of a response. Malformed responses are logged here, so that callers can simply drop them.
+fn decode_response<P, T>(
+    node_advertised_url: &Url,
+    field: Option<P>,
+    name: &'static str,
+) -> Result<T, DecodeError>
+where
+    T: TryFrom<P, Error = DecodeError>,
+{
+    field
+        .ok_or(DecodeError::MissingField(name))
+        .and_then(T::try_from)
+        .map_err(|e| {
+            log::warn!(
+                "Dropping a malformed response from {}: {}",
+                node_advertised_url,
+                e
+            );
+            e
+        })
+}
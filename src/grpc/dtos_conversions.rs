@@ -1,68 +1,391 @@
+use std::{collections::BTreeSet, convert::Infallible};
+
 use url::Url;
 
 use super::protos;
 use crate::{
-    cluster::views::{MemberView, MemberViewState, PartialClusterView},
+    cluster::{
+        digest::ClusterDigest,
+        reachability::{ReachabilityRecord, ReachabilityStatus},
+        swim::PingAck,
+        version_vector::VersionVector,
+        views::{MemberView, MemberViewState, PartialClusterView, VersionedValue},
+    },
     node::{NodeId, NodeStatus},
+    protocol::{
+        Protocol, DEFAULT_CLUSTER_NAME, MAX_SUPPORTED_PROTOCOL_VERSION,
+        MIN_SUPPORTED_PROTOCOL_VERSION,
+    },
 };
 
-impl From<protos::PartialClusterView> for PartialClusterView {
-    fn from(value: protos::PartialClusterView) -> Self {
-        PartialClusterView {
-            this_node_id: value.this_node_id.unwrap().into(),
+/// The reasons why a message received from another node can't be decoded,
+/// e.g. because it was sent by a node running an incompatible version
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeError {
+    #[error("missing field `{0}`")]
+    MissingField(&'static str),
+    #[error("invalid URL `{url}`: {source}")]
+    InvalidUrl {
+        url: String,
+        source: url::ParseError,
+    },
+    #[error("unknown node status {0}")]
+    UnknownNodeStatus(u32),
+    #[error("unknown reachability status {0}")]
+    UnknownReachabilityStatus(u32),
+    #[error("member view version {0} is out of range")]
+    VersionOutOfRange(u32),
+    #[error(
+        "unsupported gossip protocol version {0}, this node supports versions {min} to {max}",
+        min = MIN_SUPPORTED_PROTOCOL_VERSION,
+        max = MAX_SUPPORTED_PROTOCOL_VERSION
+    )]
+    UnsupportedProtocolVersion(u32),
+    #[error("message sent by a node of the foreign cluster `{0}`")]
+    ForeignCluster(String),
+}
+
+impl From<DecodeError> for tonic::Status {
+    fn from(value: DecodeError) -> Self {
+        match value {
+            DecodeError::UnsupportedProtocolVersion(_) => {
+                tonic::Status::failed_precondition(value.to_string())
+            }
+            DecodeError::ForeignCluster(_) => tonic::Status::permission_denied(value.to_string()),
+            _ => tonic::Status::invalid_argument(value.to_string()),
+        }
+    }
+}
+
+impl protos::ProtocolHeader {
+    /// The header of every message sent by a node of the given cluster
+    pub(crate) fn new(cluster_name: &str) -> Self {
+        Self {
+            cluster_name: cluster_name.to_string(),
+            ..Protocol::current().into()
+        }
+    }
+}
+
+/// Rejects messages sent by nodes of another cluster, or by peers speaking a protocol version
+/// this node no longer supports. Messages without a header come from nodes that predate protocol versioning,
+/// which belong to the [default cluster](DEFAULT_CLUSTER_NAME).
+pub(crate) fn check_header(
+    header: Option<&protos::ProtocolHeader>,
+    cluster_name: &str,
+) -> Result<(), DecodeError> {
+    let version = header.map_or(0, |h| h.version);
+    if !Protocol::is_supported(version) {
+        return Err(DecodeError::UnsupportedProtocolVersion(version));
+    }
+    match header {
+        Some(header) if header.cluster_name != cluster_name => {
+            Err(DecodeError::ForeignCluster(header.cluster_name.clone()))
+        }
+        None if cluster_name != DEFAULT_CLUSTER_NAME => Err(DecodeError::MissingField("header")),
+        _ => Ok(()),
+    }
+}
+
+/// Node ids can always be decoded
+impl From<Infallible> for DecodeError {
+    fn from(value: Infallible) -> Self {
+        match value {}
+    }
+}
+
+/// Decodes a required field
+fn required<P, T>(field: Option<P>, name: &'static str) -> Result<T, DecodeError>
+where
+    T: TryFrom<P>,
+    DecodeError: From<T::Error>,
+{
+    Ok(T::try_from(field.ok_or(DecodeError::MissingField(name))?)?)
+}
+
+impl TryFrom<protos::PartialClusterView> for PartialClusterView {
+    type Error = DecodeError;
+
+    fn try_from(value: protos::PartialClusterView) -> Result<Self, Self::Error> {
+        Ok(PartialClusterView {
+            this_node_id: required(value.this_node_id, "this_node_id")?,
             members: value
                 .members
                 .into_iter()
-                .map(|m| (m.node_id.unwrap().into(), m.member.unwrap().into()))
-                .collect(),
-        }
+                .map(|m| {
+                    Ok((
+                        required(m.node_id, "node_id")?,
+                        required(m.member, "member")?,
+                    ))
+                })
+                .collect::<Result<_, DecodeError>>()?,
+            reachability: value
+                .reachability
+                .into_iter()
+                .map(ReachabilityRecord::try_from)
+                .collect::<Result<_, _>>()?,
+            tombstones: value
+                .tombstones
+                .into_iter()
+                .map(|t| Ok((required(t.node_id, "node_id")?, t.removed_at)))
+                .collect::<Result<_, DecodeError>>()?,
+            quarantine: value.quarantine.into_iter().map(NodeId::from).collect(),
+            join_token: Some(value.join_token).filter(|token| !token.is_empty()),
+        })
     }
 }
 
-impl From<PartialClusterView> for protos::PartialClusterView {
-    fn from(value: PartialClusterView) -> Self {
+impl protos::PartialClusterView {
+    /// Encodes a view, along with the header of the message carrying it
+    pub(crate) fn new(value: PartialClusterView, header: protos::ProtocolHeader) -> Self {
         protos::PartialClusterView {
             this_node_id: Some(value.this_node_id.into()),
             members: value
                 .members
-                .into_iter()
-                .map(|(_, m)| protos::PartialClusterViewEntry {
+                .into_values()
+                .map(|m| protos::PartialClusterViewEntry {
                     node_id: Some(m.id.into()),
                     member: Some(m.into()),
                 })
                 .collect(),
+            reachability: value
+                .reachability
+                .into_iter()
+                .map(protos::ReachabilityRecord::from)
+                .collect(),
+            tombstones: value
+                .tombstones
+                .into_iter()
+                .map(|(node_id, removed_at)| protos::Tombstone {
+                    node_id: Some(node_id.into()),
+                    removed_at,
+                })
+                .collect(),
+            quarantine: value
+                .quarantine
+                .into_iter()
+                .map(protos::NodeId::from)
+                .collect(),
+            join_token: value.join_token.unwrap_or_default(),
+            header: Some(header),
+        }
+    }
+}
+
+impl TryFrom<protos::ReachabilityRecord> for ReachabilityRecord {
+    type Error = DecodeError;
+
+    fn try_from(value: protos::ReachabilityRecord) -> Result<Self, Self::Error> {
+        Ok(ReachabilityRecord {
+            observer: required(value.observer, "observer")?,
+            subject: required(value.subject, "subject")?,
+            status: u8::try_from(value.status)
+                .ok()
+                .and_then(|status| ReachabilityStatus::try_from(status).ok())
+                .ok_or(DecodeError::UnknownReachabilityStatus(value.status))?,
+            version: value.version,
+        })
+    }
+}
+
+impl From<ReachabilityRecord> for protos::ReachabilityRecord {
+    fn from(value: ReachabilityRecord) -> Self {
+        Self {
+            observer: Some(value.observer.into()),
+            subject: Some(value.subject.into()),
+            status: value.status as u32,
+            version: value.version,
+        }
+    }
+}
+
+impl TryFrom<protos::ClusterDigest> for ClusterDigest {
+    type Error = DecodeError;
+
+    fn try_from(value: protos::ClusterDigest) -> Result<Self, Self::Error> {
+        let mut digest = ClusterDigest {
+            this_node_id: required(value.this_node_id, "this_node_id")?,
+            version_vector: VersionVector::default(),
+            heartbeats: Default::default(),
+            application_state_versions: Default::default(),
+            observations_digests: Default::default(),
+            reachability_versions: Default::default(),
+            tombstones: Default::default(),
+            quarantine: Default::default(),
+        };
+        for member in value.members {
+            let node_id: NodeId = required(member.node_id, "node_id")?;
+            let version = u16::try_from(member.version)
+                .map_err(|_| DecodeError::VersionOutOfRange(member.version))?;
+            digest.version_vector.versions.insert(node_id, version);
+            digest.heartbeats.insert(node_id, member.heartbeat);
+            if member.application_state_version > 0 {
+                digest
+                    .application_state_versions
+                    .insert(node_id, member.application_state_version);
+            }
+            digest
+                .observations_digests
+                .insert(node_id, member.observations_digest);
+            if member.reachability_version > 0 {
+                digest
+                    .reachability_versions
+                    .insert(node_id, member.reachability_version);
+            }
+            if member.removed {
+                digest.tombstones.insert(node_id);
+            }
+            if member.quarantined {
+                digest.quarantine.insert(node_id);
+            }
+        }
+        Ok(digest)
+    }
+}
+
+impl protos::ClusterDigest {
+    /// Encodes a digest, along with the header of the message carrying it
+    pub(crate) fn new(value: ClusterDigest, header: protos::ProtocolHeader) -> Self {
+        let node_ids: BTreeSet<NodeId> = value
+            .version_vector
+            .versions
+            .keys()
+            .chain(value.heartbeats.keys())
+            .chain(value.reachability_versions.keys())
+            .chain(value.tombstones.iter())
+            .chain(value.quarantine.iter())
+            .cloned()
+            .collect();
+        protos::ClusterDigest {
+            this_node_id: Some(value.this_node_id.into()),
+            members: node_ids
+                .into_iter()
+                .map(|node_id| protos::MemberDigest {
+                    node_id: Some(node_id.into()),
+                    version: value
+                        .version_vector
+                        .versions
+                        .get(&node_id)
+                        .copied()
+                        .unwrap_or(0) as u32,
+                    heartbeat: value.heartbeats.get(&node_id).copied().unwrap_or(0),
+                    application_state_version: value
+                        .application_state_versions
+                        .get(&node_id)
+                        .copied()
+                        .unwrap_or(0),
+                    observations_digest: value
+                        .observations_digests
+                        .get(&node_id)
+                        .copied()
+                        .unwrap_or(0),
+                    reachability_version: value
+                        .reachability_versions
+                        .get(&node_id)
+                        .copied()
+                        .unwrap_or(0),
+                    removed: value.tombstones.contains(&node_id),
+                    quarantined: value.quarantine.contains(&node_id),
+                })
+                .collect(),
+            header: Some(header),
         }
     }
 }
 
-impl From<protos::MemberView> for MemberView {
-    fn from(value: protos::MemberView) -> Self {
-        MemberView {
-            id: value.id.unwrap().into(),
-            advertised_addr: Url::parse(&value.advertised_addr).unwrap(),
-            state: value.state.map(MemberViewState::from),
+impl TryFrom<protos::PingAck> for PingAck {
+    type Error = DecodeError;
+
+    fn try_from(value: protos::PingAck) -> Result<Self, Self::Error> {
+        Ok(PingAck {
+            this_node_id: required(value.this_node_id, "this_node_id")?,
+            heartbeat: value.heartbeat,
+            incarnation: value.incarnation,
+        })
+    }
+}
+
+impl protos::PingAck {
+    /// Encodes an acknowledgement, along with the header of the message carrying it
+    pub(crate) fn new(value: PingAck, header: protos::ProtocolHeader) -> Self {
+        protos::PingAck {
+            this_node_id: Some(value.this_node_id.into()),
+            heartbeat: value.heartbeat,
+            incarnation: value.incarnation,
+            header: Some(header),
         }
     }
 }
 
+impl TryFrom<protos::MemberView> for MemberView {
+    type Error = DecodeError;
+
+    fn try_from(value: protos::MemberView) -> Result<Self, Self::Error> {
+        Ok(MemberView {
+            id: required(value.id, "id")?,
+            advertised_addr: Url::parse(&value.advertised_addr).map_err(|source| {
+                DecodeError::InvalidUrl {
+                    url: value.advertised_addr.clone(),
+                    source,
+                }
+            })?,
+            roles: value.roles.into_iter().collect(),
+            application_state: value
+                .application_state
+                .into_iter()
+                .map(|entry| {
+                    (
+                        entry.key,
+                        VersionedValue {
+                            version: entry.version,
+                            value: entry.value,
+                        },
+                    )
+                })
+                .collect(),
+            protocol: value.protocol.map(Protocol::from).unwrap_or_default(),
+            state: value.state.map(MemberViewState::try_from).transpose()?,
+        })
+    }
+}
+
 impl From<MemberView> for protos::MemberView {
     fn from(value: MemberView) -> Self {
         Self {
             id: Some(value.id.into()),
             advertised_addr: value.advertised_addr.to_string(),
+            roles: value.roles.into_iter().collect(),
+            application_state: value
+                .application_state
+                .into_iter()
+                .map(|(key, v)| protos::ApplicationStateEntry {
+                    key,
+                    value: v.value,
+                    version: v.version,
+                })
+                .collect(),
+            protocol: Some(value.protocol.into()),
             state: value.state.map(protos::MemberViewState::from),
         }
     }
 }
 
-impl From<protos::MemberViewState> for MemberViewState {
-    fn from(value: protos::MemberViewState) -> Self {
-        MemberViewState {
-            node_status: NodeStatus::try_from(value.node_status as u8).unwrap(),
-            version: value.version as u16,
+impl TryFrom<protos::MemberViewState> for MemberViewState {
+    type Error = DecodeError;
+
+    fn try_from(value: protos::MemberViewState) -> Result<Self, Self::Error> {
+        Ok(MemberViewState {
+            node_status: u8::try_from(value.node_status)
+                .ok()
+                .and_then(|status| NodeStatus::try_from(status).ok())
+                .ok_or(DecodeError::UnknownNodeStatus(value.node_status))?,
+            version: u16::try_from(value.version)
+                .map_err(|_| DecodeError::VersionOutOfRange(value.version))?,
             heartbeat: value.heartbeat,
             observed_by: value.observed_by.into_iter().map(|n| n.into()).collect(),
-        }
+            incarnation: value.incarnation,
+            suspected: value.suspected,
+        })
     }
 }
 
@@ -73,6 +396,27 @@ impl From<MemberViewState> for protos::MemberViewState {
             version: value.version as u32,
             heartbeat: value.heartbeat,
             observed_by: value.observed_by.into_iter().map(|n| n.into()).collect(),
+            incarnation: value.incarnation,
+            suspected: value.suspected,
+        }
+    }
+}
+
+impl From<protos::ProtocolHeader> for Protocol {
+    fn from(value: protos::ProtocolHeader) -> Self {
+        Protocol {
+            version: value.version,
+            features: value.features.into_iter().collect(),
+        }
+    }
+}
+
+impl From<Protocol> for protos::ProtocolHeader {
+    fn from(value: Protocol) -> Self {
+        Self {
+            version: value.version,
+            features: value.features.into_iter().collect(),
+            cluster_name: String::new(),
         }
     }
 }
@@ -94,3 +438,116 @@ impl From<NodeId> for protos::NodeId {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[quickcheck]
+    fn partial_cluster_views_survive_a_roundtrip(view: PartialClusterView) -> bool {
+        let header = protos::ProtocolHeader::new("council");
+        PartialClusterView::try_from(protos::PartialClusterView::new(view.clone(), header)).ok()
+            == Some(view)
+    }
+
+    #[test]
+    fn malformed_views_are_rejected() {
+        let member = protos::MemberView {
+            id: Some(protos::NodeId {
+                unique_id: 1,
+                generation: 1,
+            }),
+            advertised_addr: "http://localhost:8080".to_string(),
+            state: Some(protos::MemberViewState {
+                node_status: NodeStatus::Up as u32,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let view = |member: protos::MemberView| protos::PartialClusterView {
+            this_node_id: member.id.clone(),
+            members: vec![protos::PartialClusterViewEntry {
+                node_id: member.id.clone(),
+                member: Some(member),
+            }],
+            ..Default::default()
+        };
+        assert!(PartialClusterView::try_from(view(member.clone())).is_ok());
+
+        let mut missing_id = view(member.clone());
+        missing_id.members[0].member.as_mut().unwrap().id = None;
+        assert!(matches!(
+            PartialClusterView::try_from(missing_id),
+            Err(DecodeError::MissingField("id"))
+        ));
+
+        let invalid_url = protos::MemberView {
+            advertised_addr: "not a url".to_string(),
+            ..member.clone()
+        };
+        assert!(matches!(
+            PartialClusterView::try_from(view(invalid_url)),
+            Err(DecodeError::InvalidUrl { .. })
+        ));
+
+        let unknown_status = protos::MemberView {
+            state: Some(protos::MemberViewState {
+                node_status: 42,
+                ..Default::default()
+            }),
+            ..member.clone()
+        };
+        assert!(matches!(
+            PartialClusterView::try_from(view(unknown_status)),
+            Err(DecodeError::UnknownNodeStatus(42))
+        ));
+
+        let version_out_of_range = protos::MemberView {
+            state: Some(protos::MemberViewState {
+                node_status: NodeStatus::Up as u32,
+                version: 1 << 16,
+                ..Default::default()
+            }),
+            ..member
+        };
+        assert!(matches!(
+            PartialClusterView::try_from(view(version_out_of_range)),
+            Err(DecodeError::VersionOutOfRange(65536))
+        ));
+    }
+
+    #[test]
+    fn messages_from_unsupported_versions_or_foreign_clusters_are_rejected() {
+        let header = protos::ProtocolHeader::new("production");
+        assert!(check_header(Some(&header), "production").is_ok());
+        // Nodes that predate protocol versioning don't send any header, and only belong to the default cluster
+        assert!(check_header(None, DEFAULT_CLUSTER_NAME).is_ok());
+        let rejection = check_header(None, "production").unwrap_err();
+        assert!(matches!(rejection, DecodeError::MissingField("header")));
+        assert_eq!(
+            tonic::Status::from(rejection).code(),
+            tonic::Code::InvalidArgument
+        );
+
+        let too_recent = protos::ProtocolHeader {
+            version: MAX_SUPPORTED_PROTOCOL_VERSION + 1,
+            ..header.clone()
+        };
+        let rejection = check_header(Some(&too_recent), "production").unwrap_err();
+        assert!(matches!(
+            rejection,
+            DecodeError::UnsupportedProtocolVersion(version) if version == MAX_SUPPORTED_PROTOCOL_VERSION + 1
+        ));
+        assert_eq!(
+            tonic::Status::from(rejection).code(),
+            tonic::Code::FailedPrecondition
+        );
+
+        let rejection = check_header(Some(&header), "staging").unwrap_err();
+        assert!(matches!(&rejection, DecodeError::ForeignCluster(name) if name == "production"));
+        assert_eq!(
+            tonic::Status::from(rejection).code(),
+            tonic::Code::PermissionDenied
+        );
+    }
+}
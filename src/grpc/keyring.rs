@@ -0,0 +1,468 @@
+//! Authentication of gossip messages with shared secrets, in the spirit of memberlist.
+//!
+//! When a [Keyring] is configured, every request and every response exchanged between nodes carries an HMAC-SHA256
+//! computed with the primary key of the sender. It covers the gRPC method the message belongs to, the time it was sent
+//! at, and its body exactly as it was sent on the wire, so that nodes speaking different [protocol](crate::protocol)
+//! versions still authenticate each other. Responses are also bound to the request they answer.
+//! Messages that aren't signed by any key of the receiver's keyring are rejected, as well as messages sent more than
+//! [MAX_MESSAGE_AGE] ago, or that far in the future: the clocks of the nodes must be kept roughly in sync.
+//! Messages are authenticated, not encrypted: use TLS, through the `tls` feature or a custom
+//! [TonicChannelFactory](super::TonicChannelFactory), to keep them confidential.
+//!
+//! Keys can be rotated without downtime:
+//! 1. add the new key to the keyring of every node, as a secondary key
+//! 2. make it the primary key of every node
+//! 3. remove the old key from every keyring
+use std::{
+    convert::Infallible,
+    fmt,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use hmac::{Hmac, Mac};
+use http::{HeaderMap, HeaderValue};
+use http_body::{Body, SizeHint};
+use sha2::Sha256;
+
+/// The header carrying the signature of a message, hex encoded
+const SIGNATURE_HEADER: &str = "council-signature";
+/// The header carrying the time a message was sent at, in milliseconds since the Unix epoch
+const TIMESTAMP_HEADER: &str = "council-timestamp";
+/// How far the time a message was sent at may be from the time it is received at
+pub(crate) const MAX_MESSAGE_AGE: Duration = Duration::from_secs(30);
+/// The largest body read before its signature is verified, like the largest message tonic decodes by default
+pub(crate) const MAX_BODY_LENGTH: usize = 4 * 1024 * 1024;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The shared secrets used to sign and verify gossip messages, see the [module documentation](self)
+#[derive(Clone)]
+pub struct Keyring {
+    /// The primary key comes first, and is the only one used to sign messages
+    keys: Vec<Vec<u8>>,
+}
+
+impl Keyring {
+    /// Creates a keyring with the key that signs outgoing messages
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is empty
+    pub fn new(primary_key: impl Into<Vec<u8>>) -> Self {
+        let primary_key = primary_key.into();
+        assert!(!primary_key.is_empty(), "Keys must not be empty");
+        Self {
+            keys: vec![primary_key],
+        }
+    }
+
+    /// Adds a key that is only used to verify incoming messages, e.g. while keys are being rotated
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is empty
+    pub fn with_secondary_key(mut self, key: impl Into<Vec<u8>>) -> Self {
+        let key = key.into();
+        assert!(!key.is_empty(), "Keys must not be empty");
+        self.keys.push(key);
+        self
+    }
+
+    /// Signs a message with the primary key, and attaches the signature and the current time to its headers.
+    /// Returns the signature, which the response to a request is bound to.
+    pub(crate) fn sign(
+        &self,
+        message: &SignedMessage,
+        headers: &mut HeaderMap,
+        now: SystemTime,
+    ) -> Vec<u8> {
+        let timestamp = millis_since_epoch(now);
+        let signature = mac(&self.keys[0], message, timestamp)
+            .finalize()
+            .into_bytes()
+            .to_vec();
+        headers.insert(TIMESTAMP_HEADER, HeaderValue::from(timestamp));
+        headers.insert(
+            SIGNATURE_HEADER,
+            HeaderValue::from_str(&encode_hex(&signature)).expect("Hex is a valid header value"),
+        );
+        signature
+    }
+
+    /// Checks that a message has been signed recently with any key of the keyring, and returns its signature
+    pub(crate) fn verify(
+        &self,
+        message: &SignedMessage,
+        headers: &HeaderMap,
+        now: SystemTime,
+    ) -> Result<Vec<u8>, AuthenticationError> {
+        let (Some(timestamp), Some(signature)) =
+            (headers.get(TIMESTAMP_HEADER), headers.get(SIGNATURE_HEADER))
+        else {
+            return Err(AuthenticationError::MissingSignature);
+        };
+        let timestamp: u64 = timestamp
+            .to_str()
+            .ok()
+            .and_then(|timestamp| timestamp.parse().ok())
+            .ok_or(AuthenticationError::InvalidSignature)?;
+        let signature = signature
+            .to_str()
+            .ok()
+            .and_then(decode_hex)
+            .ok_or(AuthenticationError::InvalidSignature)?;
+        if !self.keys.iter().any(|key| {
+            mac(key, message, timestamp)
+                .verify_slice(&signature)
+                .is_ok()
+        }) {
+            return Err(AuthenticationError::InvalidSignature);
+        }
+        // Checked once the timestamp is known to be genuine, so that only replays are reported as stale
+        let age = Duration::from_millis(millis_since_epoch(now).abs_diff(timestamp));
+        if age > MAX_MESSAGE_AGE {
+            return Err(AuthenticationError::StaleMessage(age));
+        }
+        Ok(signature)
+    }
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the keys themselves
+        f.debug_struct("Keyring")
+            .field("nr_of_keys", &self.keys.len())
+            .finish()
+    }
+}
+
+/// What the signature of a message covers
+pub(crate) struct SignedMessage<'a> {
+    /// The path of the gRPC method, e.g. `/council.GossipService/Ping`
+    pub(crate) path: &'a str,
+    /// The signature of the request, when the message is a response to it
+    pub(crate) request_signature: Option<&'a [u8]>,
+    /// The body of the message, as sent on the wire
+    pub(crate) body: &'a [u8],
+}
+
+fn mac(key: &[u8], message: &SignedMessage, timestamp: u64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    // Variable-length fields are prefixed with their length, so that they can't bleed into each other
+    mac.update(&(message.path.len() as u64).to_be_bytes());
+    mac.update(message.path.as_bytes());
+    mac.update(&timestamp.to_be_bytes());
+    match message.request_signature {
+        Some(request_signature) => {
+            mac.update(b"response");
+            mac.update(&(request_signature.len() as u64).to_be_bytes());
+            mac.update(request_signature);
+        }
+        None => mac.update(b"request"),
+    }
+    mac.update(message.body);
+    mac
+}
+
+fn millis_since_epoch(time: SystemTime) -> u64 {
+    let millis = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    u64::try_from(millis).unwrap_or(u64::MAX)
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Fails on odd lengths, as the last pair of digits is then out of bounds
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// A body read entirely in memory, so that it can be signed or verified before being passed on
+#[derive(Debug, Default)]
+pub(crate) struct BufferedBody {
+    data: Bytes,
+    trailers: Option<HeaderMap>,
+}
+
+impl BufferedBody {
+    /// Reads a body to its end, trailers included. Bodies longer than [MAX_BODY_LENGTH] are rejected, so that
+    /// unauthenticated peers can't make the node buffer unbounded messages.
+    pub(crate) async fn read<B>(mut body: B) -> Result<Self, tonic::Status>
+    where
+        B: Body + Unpin,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let mut data = BytesMut::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.map_err(|e| tonic::Status::from_error(e.into()))?;
+            if data.len() + chunk.remaining() > MAX_BODY_LENGTH {
+                return Err(tonic::Status::resource_exhausted(format!(
+                    "message body longer than {MAX_BODY_LENGTH} bytes"
+                )));
+            }
+            data.put(chunk);
+        }
+        let trailers = body
+            .trailers()
+            .await
+            .map_err(|e| tonic::Status::from_error(e.into()))?;
+        Ok(Self {
+            data: data.freeze(),
+            trailers,
+        })
+    }
+
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Body for BufferedBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if self.data.has_remaining() {
+            Poll::Ready(Some(Ok(std::mem::take(&mut self.data))))
+        } else {
+            Poll::Ready(None)
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Poll::Ready(Ok(self.trailers.take()))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        !self.data.has_remaining() && self.trailers.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.data.len() as u64)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuthenticationError {
+    #[error("the message is not signed")]
+    MissingSignature,
+    #[error("the message is not signed by any key of the keyring")]
+    InvalidSignature,
+    #[error("the message was sent {0:?} away from now")]
+    StaleMessage(Duration),
+    #[cfg(feature = "tls")]
+    #[error(transparent)]
+    PeerIdentity(#[from] super::tls::PeerIdentityError),
+}
+
+impl From<AuthenticationError> for tonic::Status {
+    fn from(value: AuthenticationError) -> Self {
+        tonic::Status::unauthenticated(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, time::Duration};
+
+    use tokio::time::{sleep, timeout};
+    use tonic::transport::Server;
+    use url::Url;
+
+    use super::*;
+    use crate::Council;
+
+    const PING: &str = "/council.GossipService/Ping";
+
+    fn request(path: &'static str, body: &'static [u8]) -> SignedMessage<'static> {
+        SignedMessage {
+            path,
+            request_signature: None,
+            body,
+        }
+    }
+
+    #[test]
+    fn messages_signed_with_any_key_of_the_keyring_are_accepted() {
+        let old_keyring = Keyring::new("old key");
+        let rotating_keyring = Keyring::new("new key").with_secondary_key("old key");
+        let new_keyring = Keyring::new("new key");
+        let now = SystemTime::now();
+
+        let mut headers = HeaderMap::new();
+        old_keyring.sign(&request(PING, b"ping"), &mut headers, now);
+        assert!(old_keyring
+            .verify(&request(PING, b"ping"), &headers, now)
+            .is_ok());
+        assert!(rotating_keyring
+            .verify(&request(PING, b"ping"), &headers, now)
+            .is_ok());
+        assert!(matches!(
+            new_keyring.verify(&request(PING, b"ping"), &headers, now),
+            Err(AuthenticationError::InvalidSignature)
+        ));
+
+        let mut headers = HeaderMap::new();
+        rotating_keyring.sign(&request(PING, b"ping"), &mut headers, now);
+        assert!(new_keyring
+            .verify(&request(PING, b"ping"), &headers, now)
+            .is_ok());
+        assert!(old_keyring
+            .verify(&request(PING, b"ping"), &headers, now)
+            .is_err());
+    }
+
+    #[test]
+    fn tampered_redirected_or_unsigned_messages_are_rejected() {
+        let keyring = Keyring::new("secret");
+        let now = SystemTime::now();
+        let mut headers = HeaderMap::new();
+        let request_signature = keyring.sign(&request(PING, b"ping"), &mut headers, now);
+        assert!(matches!(
+            keyring.verify(&request(PING, b"pong"), &headers, now),
+            Err(AuthenticationError::InvalidSignature)
+        ));
+        assert!(matches!(
+            keyring.verify(
+                &request("/council.GossipService/SendHeartbeat", b"ping"),
+                &headers,
+                now
+            ),
+            Err(AuthenticationError::InvalidSignature)
+        ));
+
+        let response = |request_signature| SignedMessage {
+            path: PING,
+            request_signature: Some(request_signature),
+            body: b"",
+        };
+        let mut response_headers = HeaderMap::new();
+        keyring.sign(&response(&request_signature), &mut response_headers, now);
+        assert!(keyring
+            .verify(&response(&request_signature), &response_headers, now)
+            .is_ok());
+        assert!(matches!(
+            keyring.verify(&response(b"another request"), &response_headers, now),
+            Err(AuthenticationError::InvalidSignature)
+        ));
+
+        let rejection = keyring
+            .verify(&request(PING, b"ping"), &HeaderMap::new(), now)
+            .unwrap_err();
+        assert!(matches!(rejection, AuthenticationError::MissingSignature));
+        assert_eq!(
+            tonic::Status::from(rejection).code(),
+            tonic::Code::Unauthenticated
+        );
+    }
+
+    #[test]
+    fn stale_messages_are_rejected() {
+        let keyring = Keyring::new("secret");
+        let sent_at = SystemTime::now();
+        let mut headers = HeaderMap::new();
+        keyring.sign(&request(PING, b"ping"), &mut headers, sent_at);
+
+        for received_at in [sent_at + MAX_MESSAGE_AGE, sent_at - MAX_MESSAGE_AGE] {
+            assert!(keyring
+                .verify(&request(PING, b"ping"), &headers, received_at)
+                .is_ok());
+        }
+        for received_at in [
+            sent_at + MAX_MESSAGE_AGE + Duration::from_millis(1),
+            sent_at - MAX_MESSAGE_AGE - Duration::from_millis(1),
+        ] {
+            assert!(matches!(
+                keyring.verify(&request(PING, b"ping"), &headers, received_at),
+                Err(AuthenticationError::StaleMessage(_))
+            ));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Keys must not be empty")]
+    fn empty_keys_are_refused() {
+        Keyring::new("");
+    }
+
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    fn start_node(key: &str, port: u16, peer_ports: &[u16]) -> Council {
+        let url = |port| Url::parse(&format!("http://127.0.0.1:{}", port)).unwrap();
+        let peer_nodes: Vec<Url> = peer_ports.iter().map(|port| url(*port)).collect();
+        let council = Council::builder(url(port))
+            .with_keyring(Keyring::new(key))
+            .with_peer_nodes(&peer_nodes)
+            .with_gossip_interval(Duration::from_millis(20))
+            .build();
+        let server = Server::builder().add_service(council.gossip_grpc_service());
+        tokio::spawn(server.serve(([127, 0, 0, 1], port).into()));
+        council
+    }
+
+    #[tokio::test]
+    async fn bodies_are_read_up_to_the_maximum_length() {
+        let body = http_body::Full::new(Bytes::from(vec![0; MAX_BODY_LENGTH]));
+        let body = BufferedBody::read(body).await.unwrap();
+        assert_eq!(body.data().len(), MAX_BODY_LENGTH);
+
+        let body = http_body::Full::new(Bytes::from(vec![0; MAX_BODY_LENGTH + 1]));
+        let rejection = BufferedBody::read(body).await.unwrap_err();
+        assert_eq!(rejection.code(), tonic::Code::ResourceExhausted);
+    }
+
+    #[tokio::test]
+    async fn only_nodes_sharing_a_key_gossip_with_each_other() {
+        let [port_1, port_2, port_3] = [free_port(), free_port(), free_port()];
+        let node_1 = start_node("secret", port_1, &[port_2]);
+        let node_2 = start_node("secret", port_2, &[port_1]);
+        let intruder = start_node("guess", port_3, &[port_1, port_2]);
+
+        timeout(Duration::from_secs(10), async {
+            loop {
+                let cluster = node_1.changed().await.unwrap();
+                if cluster
+                    .cluster_view
+                    .known_members
+                    .contains_key(&node_2.this_node_id)
+                {
+                    break;
+                }
+            }
+        })
+        .await
+        .expect("The nodes should have discovered each other");
+        // Leave the intruder a few more gossip rounds to try and join
+        sleep(Duration::from_millis(200)).await;
+
+        for node in [&node_1, &node_2] {
+            assert!(!node
+                .current_cluster()
+                .cluster_view
+                .known_members
+                .contains_key(&intruder.this_node_id));
+        }
+    }
+}
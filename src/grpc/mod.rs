@@ -5,7 +5,13 @@ mod protos {
 pub(crate) mod channel_factory;
 pub(crate) mod client;
 pub(crate) mod dtos_conversions;
+pub(crate) mod keyring;
 pub(crate) mod server;
+#[cfg(feature = "tls")]
+pub(crate) mod tls;
 
 pub use channel_factory::*;
+pub use keyring::{AuthenticationError, Keyring};
 pub use server::*;
+#[cfg(feature = "tls")]
+pub use tls::{PeerIdentityError, TlsConfig, TlsTonicChannelFactory};
@@ -1,12 +1,50 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{Mutex, PoisonError},
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
+
+use http_body::Body as _;
 pub use protos::gossip_service_server::GossipServiceServer;
 use tokio::sync::{mpsc::Sender, oneshot};
-use tonic::{async_trait, Response, Status};
+#[cfg(feature = "tls")]
+use tonic::transport::server::TlsConnectInfo;
+use tonic::{
+    async_trait,
+    body::BoxBody,
+    server::NamedService,
+    transport::{server::TcpConnectInfo, Body},
+    Request, Response, Status,
+};
+use tower_service::Service;
+use url::Url;
+
+#[cfg(feature = "tls")]
+use super::{client::ADVERTISED_URL_METADATA_KEY, tls::verify_peer_identity};
+use super::{
+    dtos_conversions::{check_header, DecodeError},
+    keyring::{BufferedBody, SignedMessage},
+    protos, AuthenticationError, Keyring,
+};
+use crate::{cluster::admission::AdmissionDenied, Council, Message};
 
-use super::protos;
-use crate::{Council, Message};
+/// How long contacts from the same foreign cluster and address are reported only once
+const FOREIGN_CLUSTER_CONTACT_COOLDOWN: Duration = Duration::from_secs(60);
+/// How many foreign cluster contacts are remembered at most, so that a flood of them can't exhaust memory
+const MAX_FOREIGN_CLUSTER_CONTACTS: usize = 1024;
 
 pub struct CouncilGrpcServer {
     main_thread_message_sender: Sender<Message>,
+    cluster_name: String,
+    #[cfg(feature = "tls")]
+    verify_peer_identity: bool,
+    /// When each foreign cluster was last reported, by cluster name and address of the node that made contact
+    foreign_cluster_contacts: Mutex<HashMap<(String, Option<IpAddr>), Instant>>,
 }
 
 impl Council {
@@ -16,11 +54,13 @@ impl Council {
     ///
     /// # Examples
     ///
-    /// ```rust
-    /// use tonic::transport::Server
+    /// ```no_run
+    /// use tonic::transport::Server;
     /// use url::Url;
     /// use council::*;
     ///
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
     /// let socket_addr =  "[::1]:50051".parse()?;
     /// let advertised_url = Url::parse("http://localhost:50051")?;
     /// let council = Council::builder(advertised_url).build();
@@ -28,13 +68,204 @@ impl Council {
     ///     .add_service(council.gossip_grpc_service())
     ///     .serve(socket_addr)
     ///     .await?;
+    /// # Ok(())
+    /// # }
     /// ```
     ///
-    pub fn gossip_grpc_service(&self) -> GossipServiceServer<CouncilGrpcServer> {
+    pub fn gossip_grpc_service(&self) -> GossipGrpcService {
         let server = CouncilGrpcServer {
             main_thread_message_sender: self.main_thread_message_sender.clone(),
+            cluster_name: self.cluster_name.clone(),
+            #[cfg(feature = "tls")]
+            verify_peer_identity: self
+                .tls
+                .as_ref()
+                .is_some_and(|tls| tls.verifies_peer_identity()),
+            foreign_cluster_contacts: Mutex::new(HashMap::new()),
+        };
+        GossipGrpcService {
+            server: GossipServiceServer::new(server),
+            keyring: self.keyring.clone(),
+        }
+    }
+}
+
+/// The gossip service of a [Council], to add to a Tonic [Server](tonic::transport::Server).
+/// When a [Keyring] is configured, it rejects requests that aren't signed by any of its keys, and signs responses
+/// with its primary key.
+#[derive(Clone)]
+pub struct GossipGrpcService {
+    server: GossipServiceServer<CouncilGrpcServer>,
+    keyring: Option<Keyring>,
+}
+
+impl NamedService for GossipGrpcService {
+    const NAME: &'static str = <GossipServiceServer<CouncilGrpcServer> as NamedService>::NAME;
+}
+
+impl Service<http::Request<Body>> for GossipGrpcService {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Service::<http::Request<Body>>::poll_ready(&mut self.server, cx)
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        let mut server = self.server.clone();
+        let Some(keyring) = self.keyring.clone() else {
+            return Box::pin(server.call(request));
         };
-        GossipServiceServer::new(server)
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let body = match BufferedBody::read(body).await {
+                Ok(body) => body,
+                Err(status) => return Ok(status.to_http()),
+            };
+            let path = parts.uri.path().to_string();
+            let request = SignedMessage {
+                path: &path,
+                request_signature: None,
+                body: body.data(),
+            };
+            let request_signature =
+                match keyring.verify(&request, &parts.headers, SystemTime::now()) {
+                    Ok(signature) => signature,
+                    Err(e) => {
+                        log::warn!(
+                            "Rejecting an unauthenticated message from {:?}: {}",
+                            remote_addr(&parts.extensions),
+                            e
+                        );
+                        return Ok(Status::from(e).to_http());
+                    }
+                };
+
+            let response = server.call(http::Request::from_parts(parts, body)).await?;
+            let (mut parts, body) = response.into_parts();
+            let body = match BufferedBody::read(body).await {
+                Ok(body) => body,
+                Err(status) => return Ok(status.to_http()),
+            };
+            let response = SignedMessage {
+                path: &path,
+                request_signature: Some(&request_signature),
+                body: body.data(),
+            };
+            keyring.sign(&response, &mut parts.headers, SystemTime::now());
+            Ok(http::Response::from_parts(
+                parts,
+                body.map_err(|never| match never {}).boxed_unsync(),
+            ))
+        })
+    }
+}
+
+/// The address of the sender of a request, like [Request::remote_addr]
+fn remote_addr(extensions: &http::Extensions) -> Option<SocketAddr> {
+    let tcp_connect_info = extensions.get::<TcpConnectInfo>();
+    #[cfg(feature = "tls")]
+    let tcp_connect_info = tcp_connect_info.or_else(|| {
+        extensions
+            .get::<TlsConnectInfo<TcpConnectInfo>>()
+            .map(TlsConnectInfo::get_ref)
+    });
+    tcp_connect_info.and_then(TcpConnectInfo::remote_addr)
+}
+
+impl CouncilGrpcServer {
+    fn header(&self) -> protos::ProtocolHeader {
+        protos::ProtocolHeader::new(&self.cluster_name)
+    }
+
+    /// Rejects requests whose sender presents a certificate that doesn't match its identity,
+    /// when [peer identities](super::TlsConfig) are verified.
+    /// Requests that aren't signed by the [Keyring] have already been rejected by the [GossipGrpcService].
+    fn authenticate<M>(
+        &self,
+        #[allow(unused_variables)] request: &Request<M>,
+        #[allow(unused_variables)] sender: Option<&protos::NodeId>,
+    ) -> Result<(), AuthenticationError> {
+        #[cfg(feature = "tls")]
+        if self.verify_peer_identity {
+            let peer_certificates = request.peer_certs();
+            let advertised_url = request
+                .metadata()
+                .get(ADVERTISED_URL_METADATA_KEY)
+                .and_then(|url| url.to_str().ok());
+            if let Err(e) = verify_peer_identity(
+                peer_certificates.as_deref().map(Vec::as_slice),
+                advertised_url,
+                sender,
+            ) {
+                log::warn!(
+                    "Rejecting an unauthenticated message from {:?}: {}",
+                    request.remote_addr(),
+                    e
+                );
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects requests sent by nodes of another cluster, or speaking an unsupported protocol version.
+    /// Contacts from foreign clusters are reported as [ClusterEvent](crate::ClusterEvent)s, once per cluster and
+    /// address every [FOREIGN_CLUSTER_CONTACT_COOLDOWN].
+    fn check_header(
+        &self,
+        remote_addr: Option<SocketAddr>,
+        header: Option<&protos::ProtocolHeader>,
+    ) -> Result<(), DecodeError> {
+        match check_header(header, &self.cluster_name) {
+            Ok(()) => Ok(()),
+            Err(DecodeError::ForeignCluster(cluster_name)) => {
+                if self.should_report_foreign_cluster_contact(&cluster_name, remote_addr) {
+                    log::warn!(
+                        "Rejecting messages from {:?}, a node of the foreign cluster `{}`",
+                        remote_addr,
+                        cluster_name
+                    );
+                    // Reports are best effort: they are dropped rather than delaying the rejection
+                    let _ =
+                        self.main_thread_message_sender
+                            .try_send(Message::ForeignClusterContact {
+                                cluster_name: cluster_name.clone(),
+                                remote_addr,
+                            });
+                }
+                Err(DecodeError::ForeignCluster(cluster_name))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Records a contact from a foreign cluster, and tells whether it hasn't been reported recently
+    fn should_report_foreign_cluster_contact(
+        &self,
+        cluster_name: &str,
+        remote_addr: Option<SocketAddr>,
+    ) -> bool {
+        let now = Instant::now();
+        let mut contacts = self
+            .foreign_cluster_contacts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let key = (cluster_name.to_string(), remote_addr.map(|addr| addr.ip()));
+        if contacts.get(&key).is_some_and(|reported_at| {
+            now.duration_since(*reported_at) < FOREIGN_CLUSTER_CONTACT_COOLDOWN
+        }) {
+            return false;
+        }
+        contacts.retain(|_, reported_at| {
+            now.duration_since(*reported_at) < FOREIGN_CLUSTER_CONTACT_COOLDOWN
+        });
+        if contacts.len() >= MAX_FOREIGN_CLUSTER_CONTACTS {
+            return false;
+        }
+        contacts.insert(key, now);
+        true
     }
 }
 
@@ -44,21 +275,191 @@ impl protos::gossip_service_server::GossipService for CouncilGrpcServer {
         &self,
         request: tonic::Request<protos::PartialClusterView>,
     ) -> Result<tonic::Response<protos::PartialClusterView>, tonic::Status> {
+        let sender = request.get_ref().this_node_id.as_ref();
+        self.authenticate(&request, sender)?;
+        self.check_header(request.remote_addr(), request.get_ref().header.as_ref())?;
+        let incoming_cluster_view = request.into_inner().try_into()?;
         let (reply_tx, reply_rx) = oneshot::channel();
-        // TODO: handle error
         self.main_thread_message_sender
             .send(Message::ReconcileClusterView {
-                incoming_cluster_view: request.into_inner().into(),
+                incoming_cluster_view,
                 reconciled_cluster_view_reply: Some(reply_tx),
             })
             .await
             .map_err(|e| Status::unavailable(e.to_string()))?;
 
         let reply = reply_rx
+            .await
+            .map_err(|e| Status::from_error(Box::new(e)))?;
+
+        Ok(Response::new(protos::PartialClusterView::new(
+            reply,
+            self.header(),
+        )))
+    }
+
+    async fn exchange_digests(
+        &self,
+        request: tonic::Request<protos::ClusterDigest>,
+    ) -> Result<tonic::Response<protos::DigestExchange>, tonic::Status> {
+        let sender = request.get_ref().this_node_id.as_ref();
+        self.authenticate(&request, sender)?;
+        self.check_header(request.remote_addr(), request.get_ref().header.as_ref())?;
+        let incoming_digest = request.into_inner().try_into()?;
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.main_thread_message_sender
+            .send(Message::ReconcileDigest {
+                incoming_digest: Box::new(incoming_digest),
+                incoming_delta: None,
+                reply: reply_tx,
+            })
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+
+        let reply = match reply_rx
             .await
             .map_err(|e| Status::from_error(Box::new(e)))?
+        {
+            Ok((digest, delta)) => protos::DigestExchange {
+                digest: Some(protos::ClusterDigest::new(digest, self.header())),
+                delta: Some(protos::PartialClusterView::new(delta, self.header())),
+                admission_denied: String::new(),
+            },
+            Err(AdmissionDenied(reason)) => protos::DigestExchange {
+                admission_denied: reason,
+                ..Default::default()
+            },
+        };
+        Ok(Response::new(reply))
+    }
+
+    async fn push_delta(
+        &self,
+        request: tonic::Request<protos::PartialClusterView>,
+    ) -> Result<tonic::Response<protos::PushDeltaAck>, tonic::Status> {
+        let sender = request.get_ref().this_node_id.as_ref();
+        self.authenticate(&request, sender)?;
+        self.check_header(request.remote_addr(), request.get_ref().header.as_ref())?;
+        let incoming_cluster_view = request.into_inner().try_into()?;
+        self.main_thread_message_sender
+            .send(Message::ReconcileClusterView {
+                incoming_cluster_view,
+                reconciled_cluster_view_reply: None,
+            })
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+
+        Ok(Response::new(protos::PushDeltaAck {}))
+    }
+
+    async fn probe(
+        &self,
+        request: tonic::Request<protos::ProbeRequest>,
+    ) -> Result<tonic::Response<protos::ProbeReply>, tonic::Status> {
+        let sender = request.get_ref().this_node_id.as_ref();
+        self.authenticate(&request, sender)?;
+        self.check_header(request.remote_addr(), request.get_ref().header.as_ref())?;
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.main_thread_message_sender
+            .send(Message::Probe { reply: reply_tx })
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+
+        let (this_node_id, cluster_formed) = reply_rx
+            .await
+            .map_err(|e| Status::from_error(Box::new(e)))?;
+
+        Ok(Response::new(protos::ProbeReply {
+            this_node_id: Some(this_node_id.into()),
+            cluster_formed,
+            header: Some(self.header()),
+        }))
+    }
+
+    async fn ping(
+        &self,
+        request: tonic::Request<protos::PingRequest>,
+    ) -> Result<tonic::Response<protos::PingAck>, tonic::Status> {
+        self.authenticate(&request, None)?;
+        self.check_header(request.remote_addr(), request.get_ref().header.as_ref())?;
+        let target = request
+            .into_inner()
+            .target
+            .ok_or_else(|| Status::invalid_argument("Missing ping target"))?
             .into();
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.main_thread_message_sender
+            .send(Message::Ping {
+                target,
+                reply: reply_tx,
+            })
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
 
-        Ok(Response::new(reply))
+        match reply_rx
+            .await
+            .map_err(|e| Status::from_error(Box::new(e)))?
+        {
+            Some(ack) => Ok(Response::new(protos::PingAck::new(ack, self.header()))),
+            None => Err(Status::not_found(format!("This node is not {}", target))),
+        }
+    }
+
+    async fn ping_req(
+        &self,
+        request: tonic::Request<protos::IndirectPingRequest>,
+    ) -> Result<tonic::Response<protos::PingAck>, tonic::Status> {
+        self.authenticate(&request, None)?;
+        self.check_header(request.remote_addr(), request.get_ref().header.as_ref())?;
+        let request = request.into_inner();
+        let target = request
+            .target
+            .ok_or_else(|| Status::invalid_argument("Missing ping target"))?
+            .into();
+        let target_advertised_url = Url::parse(&request.target_advertised_url)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.main_thread_message_sender
+            .send(Message::PingReq {
+                target,
+                target_advertised_url,
+                reply: reply_tx,
+            })
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+
+        match reply_rx
+            .await
+            .map_err(|e| Status::from_error(Box::new(e)))?
+        {
+            Some(ack) => Ok(Response::new(protos::PingAck::new(ack, self.header()))),
+            None => Err(Status::deadline_exceeded(format!(
+                "{} didn't acknowledge the ping",
+                target
+            ))),
+        }
+    }
+
+    async fn send_heartbeat(
+        &self,
+        request: tonic::Request<protos::Heartbeat>,
+    ) -> Result<tonic::Response<protos::HeartbeatAck>, tonic::Status> {
+        let sender = request.get_ref().this_node_id.as_ref();
+        self.authenticate(&request, sender)?;
+        self.check_header(request.remote_addr(), request.get_ref().header.as_ref())?;
+        let request = request.into_inner();
+        let from = request
+            .this_node_id
+            .ok_or_else(|| Status::invalid_argument("Missing node id"))?
+            .into();
+        self.main_thread_message_sender
+            .send(Message::Heartbeat {
+                from,
+                heartbeat: request.heartbeat,
+            })
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+
+        Ok(Response::new(protos::HeartbeatAck {}))
     }
 }
@@ -0,0 +1,335 @@
+//! Built-in mutual TLS between the nodes of the cluster, enabled by the `tls` feature.
+//!
+//! A [TlsConfig] holds the certificate authority of the cluster and the certificate of the running node. It produces
+//! both the [TonicChannelFactory] used to reach other nodes and the [ServerTlsConfig] of the server hosting
+//! [the gossip service](crate::Council::gossip_grpc_service), so that every node presents its certificate and only
+//! accepts certificates issued by the same authority.
+//!
+//! On top of that, the identity of peers can be checked against their membership:
+//! - when connecting to a node, its certificate must be valid for the host of the URL it advertises
+//! - when accepting a request, the certificate of the sender must be valid for the host of the URL it advertises,
+//!   and the [NodeId] it claims must derive from that URL
+use std::{error::Error, str::FromStr, time::SystemTime};
+
+use tonic::{
+    async_trait,
+    transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity, ServerTlsConfig},
+};
+use url::{Host, Url};
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
+
+use super::{protos, TonicChannelFactory, TonicChannelFactoryCache};
+use crate::node::NodeId;
+
+/// The mutual TLS settings of the running node, see the [module documentation](self)
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    ca_certificate: Certificate,
+    identity: Identity,
+    verify_peer_identity: bool,
+}
+
+impl TlsConfig {
+    /// Builds a configuration from PEM-encoded files: the certificate of the authority that issues the certificates
+    /// of all the nodes of the cluster, then the certificate and the private key of the running node.
+    /// Peer identities are verified by default.
+    pub fn from_pem(
+        ca_certificate: impl AsRef<[u8]>,
+        certificate: impl AsRef<[u8]>,
+        private_key: impl AsRef<[u8]>,
+    ) -> Self {
+        Self {
+            ca_certificate: Certificate::from_pem(ca_certificate),
+            identity: Identity::from_pem(certificate, private_key),
+            verify_peer_identity: true,
+        }
+    }
+
+    /// Sets whether the certificate of the sender of a request must match the URL and the [NodeId] it claims.
+    /// When disabled, any certificate issued by the authority of the cluster is accepted.
+    pub fn with_peer_identity_verification(mut self, verify_peer_identity: bool) -> Self {
+        self.verify_peer_identity = verify_peer_identity;
+        self
+    }
+
+    pub fn verifies_peer_identity(&self) -> bool {
+        self.verify_peer_identity
+    }
+
+    /// The TLS configuration of the server hosting the gossip service.
+    /// Clients must present a certificate issued by the authority of the cluster.
+    pub fn server_tls_config(&self) -> ServerTlsConfig {
+        ServerTlsConfig::new()
+            .identity(self.identity.clone())
+            .client_ca_root(self.ca_certificate.clone())
+    }
+
+    /// The TLS configuration of the channels opened to other nodes.
+    /// The certificate of the server is checked against the host of the URL it is reached at.
+    pub fn client_tls_config(&self) -> ClientTlsConfig {
+        ClientTlsConfig::new()
+            .ca_certificate(self.ca_certificate.clone())
+            .identity(self.identity.clone())
+    }
+
+    /// Builds a cached [TonicChannelFactory] opening TLS channels
+    pub fn channel_factory(&self) -> impl TonicChannelFactory {
+        TonicChannelFactoryCache::new(TlsTonicChannelFactory {
+            client_tls_config: self.client_tls_config(),
+        })
+    }
+}
+
+/// A [TonicChannelFactory] opening TLS channels, see [TlsConfig::channel_factory]
+pub struct TlsTonicChannelFactory {
+    client_tls_config: ClientTlsConfig,
+}
+
+#[async_trait]
+impl TonicChannelFactory for TlsTonicChannelFactory {
+    async fn channel_for_url(
+        &self,
+        url: Url,
+    ) -> Result<Channel, Box<dyn Error + Send + Sync + 'static>> {
+        let endpoint =
+            Endpoint::from_str(url.as_str())?.tls_config(self.client_tls_config.clone())?;
+        Ok(endpoint.connect().await?)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PeerIdentityError {
+    #[error("the peer hasn't presented any certificate")]
+    MissingCertificate,
+    #[error("the peer hasn't declared its advertised URL")]
+    MissingAdvertisedUrl,
+    #[error("invalid advertised URL `{0}`")]
+    InvalidAdvertisedUrl(String),
+    #[error("the certificate of the peer cannot be parsed")]
+    InvalidCertificate,
+    #[error("the certificate of the peer is not valid for {0}")]
+    UrlNotCovered(Url),
+    #[error("node {node_id} cannot be reached at {url}")]
+    NodeIdMismatch { node_id: NodeId, url: Url },
+}
+
+impl From<PeerIdentityError> for tonic::Status {
+    fn from(value: PeerIdentityError) -> Self {
+        tonic::Status::unauthenticated(value.to_string())
+    }
+}
+
+/// Checks that the certificate presented by the sender of a request is valid for the URL it advertises,
+/// and that the node it claims to be, if any, is reached at that URL
+pub(crate) fn verify_peer_identity(
+    peer_certificates: Option<&[Certificate]>,
+    advertised_url: Option<&str>,
+    sender: Option<&protos::NodeId>,
+) -> Result<(), PeerIdentityError> {
+    let certificate = peer_certificates
+        .and_then(|certificates| certificates.first())
+        .ok_or(PeerIdentityError::MissingCertificate)?;
+    let advertised_url = advertised_url.ok_or(PeerIdentityError::MissingAdvertisedUrl)?;
+    let advertised_url = Url::parse(advertised_url)
+        .map_err(|_| PeerIdentityError::InvalidAdvertisedUrl(advertised_url.to_string()))?;
+
+    // Tonic exposes the DER encoding of peer certificates
+    let (_, certificate) = X509Certificate::from_der(certificate.get_ref())
+        .map_err(|_| PeerIdentityError::InvalidCertificate)?;
+    let names = certificate
+        .subject_alternative_name()
+        .map_err(|_| PeerIdentityError::InvalidCertificate)?
+        .map(|san| san.value.general_names.clone())
+        .unwrap_or_default();
+    let host = advertised_url
+        .host()
+        .ok_or_else(|| PeerIdentityError::UrlNotCovered(advertised_url.clone()))?;
+    if !names.iter().any(|name| covers(name, &host)) {
+        return Err(PeerIdentityError::UrlNotCovered(advertised_url));
+    }
+
+    if let Some(sender) = sender {
+        // The unique id of a node is a hash of its URL, whatever its generation
+        let expected = NodeId::from_url(&advertised_url, SystemTime::UNIX_EPOCH);
+        if sender.unique_id != expected.unique_id {
+            return Err(PeerIdentityError::NodeIdMismatch {
+                node_id: sender.clone().into(),
+                url: advertised_url,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Tells whether a subject alternative name covers a host, supporting wildcards in the leftmost label of DNS names
+fn covers(name: &GeneralName, host: &Host<&str>) -> bool {
+    match (name, host) {
+        (GeneralName::DNSName(name), Host::Domain(domain)) => {
+            let name = name.to_ascii_lowercase();
+            let domain = domain.to_ascii_lowercase();
+            match name.strip_prefix("*.") {
+                Some(suffix) => domain
+                    .split_once('.')
+                    .is_some_and(|(_, domain_suffix)| domain_suffix == suffix),
+                None => name == domain,
+            }
+        }
+        (GeneralName::IPAddress(bytes), Host::Ipv4(ip)) => *bytes == ip.octets(),
+        (GeneralName::IPAddress(bytes), Host::Ipv6(ip)) => *bytes == ip.octets(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, time::Duration};
+
+    use rcgen::{BasicConstraints, CertificateParams, IsCa};
+    use tokio::time::timeout;
+    use tonic::transport::Server;
+
+    use super::*;
+    use crate::{grpc::DefaultTonicChannelFactory, Council};
+
+    /// A certificate authority, and certificates it issues
+    struct TestAuthority {
+        ca: rcgen::Certificate,
+    }
+
+    impl TestAuthority {
+        fn new() -> Self {
+            let mut params = CertificateParams::new(vec![]);
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            Self {
+                ca: rcgen::Certificate::from_params(params).unwrap(),
+            }
+        }
+
+        /// Issues the certificate of a node, valid for the given names
+        fn issue(&self, names: &[&str]) -> rcgen::Certificate {
+            rcgen::Certificate::from_params(CertificateParams::new(
+                names.iter().map(|n| n.to_string()).collect::<Vec<_>>(),
+            ))
+            .unwrap()
+        }
+
+        fn tls_config(&self, names: &[&str]) -> TlsConfig {
+            let certificate = self.issue(names);
+            TlsConfig::from_pem(
+                self.ca.serialize_pem().unwrap(),
+                certificate.serialize_pem_with_signer(&self.ca).unwrap(),
+                certificate.serialize_private_key_pem(),
+            )
+        }
+    }
+
+    #[test]
+    fn peer_certificates_must_match_the_advertised_url_and_node_id() {
+        let authority = TestAuthority::new();
+        let certificate = authority.issue(&["node-1.council.local", "*.workers.local"]);
+        // Tonic exposes the DER encoding of peer certificates through this constructor
+        let certificates = [Certificate::from_pem(
+            certificate
+                .serialize_der_with_signer(&authority.ca)
+                .unwrap(),
+        )];
+        let url = "https://node-1.council.local:8080/";
+        let node_id: protos::NodeId =
+            NodeId::from_url(&Url::parse(url).unwrap(), SystemTime::now()).into();
+
+        assert!(verify_peer_identity(Some(&certificates), Some(url), Some(&node_id)).is_ok());
+        assert!(verify_peer_identity(
+            Some(&certificates),
+            Some("https://worker-7.workers.local:8080/"),
+            None
+        )
+        .is_ok());
+        assert!(matches!(
+            verify_peer_identity(
+                Some(&certificates),
+                Some("https://node-2.council.local:8080/"),
+                None
+            ),
+            Err(PeerIdentityError::UrlNotCovered(_))
+        ));
+        assert!(matches!(
+            verify_peer_identity(
+                Some(&certificates),
+                Some("https://node-1.council.local:9090/"),
+                Some(&node_id)
+            ),
+            Err(PeerIdentityError::NodeIdMismatch { .. })
+        ));
+        assert!(matches!(
+            verify_peer_identity(None, Some(url), None),
+            Err(PeerIdentityError::MissingCertificate)
+        ));
+        assert!(matches!(
+            verify_peer_identity(Some(&certificates), None, None),
+            Err(PeerIdentityError::MissingAdvertisedUrl)
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "TLS can't be configured along with a custom TonicChannelFactory")]
+    fn tls_cannot_replace_a_custom_channel_factory() {
+        Council::builder(Url::parse("https://localhost:8080").unwrap())
+            .with_tonic_channel_factory(DefaultTonicChannelFactory::new())
+            .with_tls(TestAuthority::new().tls_config(&["localhost"]));
+    }
+
+    #[test]
+    #[should_panic(expected = "TLS can't be configured along with a custom TonicChannelFactory")]
+    fn custom_channel_factories_cannot_replace_tls() {
+        Council::builder(Url::parse("https://localhost:8080").unwrap())
+            .with_tls(TestAuthority::new().tls_config(&["localhost"]))
+            .with_tonic_channel_factory(DefaultTonicChannelFactory::new());
+    }
+
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    fn start_node(authority: &TestAuthority, port: u16, peer_ports: &[u16]) -> Council {
+        let url = |port| Url::parse(&format!("https://localhost:{}", port)).unwrap();
+        let peer_nodes: Vec<Url> = peer_ports.iter().map(|port| url(*port)).collect();
+        let council = Council::builder(url(port))
+            .with_tls(authority.tls_config(&["localhost"]))
+            .with_peer_nodes(&peer_nodes)
+            .with_gossip_interval(Duration::from_millis(20))
+            .build();
+        let server = Server::builder()
+            .tls_config(council.server_tls_config().unwrap())
+            .unwrap()
+            .add_service(council.gossip_grpc_service());
+        tokio::spawn(server.serve(([127, 0, 0, 1], port).into()));
+        council
+    }
+
+    #[tokio::test]
+    async fn nodes_gossip_over_mutual_tls() {
+        let authority = TestAuthority::new();
+        let [port_1, port_2] = [free_port(), free_port()];
+        let node_1 = start_node(&authority, port_1, &[port_2]);
+        let node_2 = start_node(&authority, port_2, &[port_1]);
+
+        timeout(Duration::from_secs(10), async {
+            loop {
+                let cluster = node_1.changed().await.unwrap();
+                if cluster
+                    .cluster_view
+                    .known_members
+                    .contains_key(&node_2.this_node_id)
+                {
+                    break;
+                }
+            }
+        })
+        .await
+        .expect("The nodes should have discovered each other");
+    }
+}
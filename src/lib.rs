@@ -1,18 +1,28 @@
-use std::{collections::HashMap, error::Error, ops::Deref, sync::Arc};
+use std::{
+    collections::HashMap,
+    error::Error,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
-use cluster::{views::PartialClusterView, Cluster};
-use grpc::{
-    client::{CouncilClient, HeartbeatMessage},
-    TonicChannelFactory,
+use cluster::{
+    admission::AdmissionDenied,
+    digest::ClusterDigest,
+    swim::{PingAck, PingTarget},
+    views::PartialClusterView,
+    Cluster,
 };
+use events::{EventPublisher, MembershipSnapshot};
+use grpc::{client::CouncilClient, Keyring, TonicChannelFactory};
 use node::NodeId;
 use tokio::{
     select,
-    sync::{broadcast, mpsc, oneshot},
+    sync::{mpsc, oneshot, watch},
     task::JoinHandle,
-    time::Interval,
+    time::{timeout, Interval},
 };
-use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
 use url::Url;
 
 #[cfg(test)]
@@ -22,9 +32,6 @@ extern crate quickcheck;
 #[macro_use(quickcheck)]
 extern crate quickcheck_macros;
 
-#[macro_use]
-extern crate log;
-
 #[cfg(feature = "serde")]
 #[macro_use]
 extern crate serde_with;
@@ -32,17 +39,29 @@ extern crate serde_with;
 mod builder;
 
 pub mod cluster;
+pub mod events;
 pub mod grpc;
 pub mod node;
+pub mod protocol;
+
+pub use self::{
+    builder::*,
+    events::{ClusterEvent, SubscriptionMode},
+};
 
-pub use self::builder::*;
+const EVENT_SUBSCRIBER_BUFFER_SIZE: usize = 16;
 
 pub struct Council {
     pub this_node_id: NodeId,
-    cluster_events_sender: broadcast::Sender<ClusterEvent>,
+    cluster_name: String,
+    keyring: Option<Keyring>,
+    #[cfg(feature = "tls")]
+    tls: Option<grpc::TlsConfig>,
     tonic_channel_factory: Arc<dyn TonicChannelFactory + Send + Sync>,
     main_thread_message_sender: mpsc::Sender<Message>,
-    _main_thread: JoinHandle<()>,
+    event_publisher: EventPublisher,
+    cluster_receiver: watch::Receiver<Arc<Cluster>>,
+    main_thread: JoinHandle<()>,
 }
 
 impl Council {
@@ -57,23 +76,133 @@ impl Council {
         self.tonic_channel_factory.as_ref()
     }
 
+    /// The TLS configuration of the server hosting [the gossip service](Council::gossip_grpc_service),
+    /// when [mutual TLS](CouncilBuilder::with_tls) is enabled.
+    ///
+    /// ```no_run
+    /// # use tonic::transport::Server;
+    /// # use council::Council;
+    /// # fn f(council: Council) -> Result<(), Box<dyn std::error::Error>> {
+    /// let server = Server::builder()
+    ///     .tls_config(council.server_tls_config().expect("TLS is enabled"))?
+    ///     .add_service(council.gossip_grpc_service());
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "tls")]
+    pub fn server_tls_config(&self) -> Option<tonic::transport::ServerTlsConfig> {
+        self.tls.as_ref().map(grpc::TlsConfig::server_tls_config)
+    }
+
     /// Subscribes to cluster events and returns a stream of events happening in the cluster.
     /// This function can be called any number of times, from multiple threads. Each call
     /// will return a new stream that produces events starting from the moment the stream was created
     /// (events that happened before the stream was created won't be received)
     ///
-    /// Every time the membership status of a node changes, all subscribing streams will receive a [ClusterEvent], containing
-    /// a pointer to the state of the cluster at the time the event was produced.
+    /// Every time the membership of the cluster changes (a member joins, is moved up, leaves, becomes unreachable ...),
+    /// all subscribing streams will receive the corresponding [ClusterEvent]s. Gossip exchanges that don't change
+    /// anything don't produce any event.
     /// Drop the stream to cancel the subscription
+    ///
+    /// This is equivalent to calling [Council::subscribe] with [SubscriptionMode::NewEventsOnly].
     pub fn events(&self) -> impl Stream<Item = ClusterEvent> + Send + Sync {
-        BroadcastStream::new(self.cluster_events_sender.subscribe()).filter_map(|i| i.ok())
+        self.subscribe(SubscriptionMode::NewEventsOnly)
+    }
+
+    /// Subscribes to cluster events, using the given [SubscriptionMode].
+    /// With [SubscriptionMode::InitialStateAsEvents], the stream starts with the current membership of the cluster,
+    /// expressed as synthetic events, so that new subscribers don't have to fetch the state of the cluster separately.
+    ///
+    /// A subscriber that doesn't consume events fast enough may fall behind. Instead of silently missing events,
+    /// the subscriber is then resynchronized from a fresh snapshot of the cluster: it receives the events that lead
+    /// from the last state it has seen to the current state.
+    /// Drop the stream to cancel the subscription
+    pub fn subscribe(
+        &self,
+        mode: SubscriptionMode,
+    ) -> impl Stream<Item = ClusterEvent> + Send + Sync {
+        let (tx, rx) = mpsc::channel(EVENT_SUBSCRIBER_BUFFER_SIZE);
+        // The subscription is registered right away, so that no event published from now on is missed.
+        // Once the main loop has stopped, the stream ends immediately.
+        if let Some(subscription) = self.event_publisher.subscribe() {
+            tokio::spawn(events::forward_events(
+                self.event_publisher.clone(),
+                subscription,
+                mode,
+                tx,
+            ));
+        }
+        ReceiverStream::new(rx)
     }
 
     /// Returns a clone of the cluster's state
     pub async fn cluster(&self) -> Result<Cluster, Box<dyn Error>> {
+        Ok(Cluster::clone(&self.current_cluster()))
+    }
+
+    /// Returns the latest snapshot of the cluster's state.
+    /// The main loop publishes a new snapshot after every change, so this doesn't require any coordination
+    /// with the main loop and can be called as often as needed.
+    /// The heartbeat of the running node doesn't trigger a new snapshot on its own, so it may lag behind.
+    pub fn current_cluster(&self) -> Arc<Cluster> {
+        Arc::clone(&self.cluster_receiver.borrow())
+    }
+
+    /// Waits for the next change in the cluster's state, then returns the new snapshot.
+    /// Fails if this instance has stopped, for example after [leaving the cluster](Council::leave).
+    pub async fn changed(&self) -> Result<Arc<Cluster>, Box<dyn Error>> {
+        let mut cluster_receiver = self.cluster_receiver.clone();
+        cluster_receiver.borrow_and_update();
+        cluster_receiver.changed().await?;
+        let cluster = Arc::clone(&cluster_receiver.borrow());
+        Ok(cluster)
+    }
+
+    /// Publishes an entry of the application state of the running node, e.g. its load or whether it is ready to serve requests.
+    /// The entry is gossiped along with the membership of the node, and other nodes are notified of the change
+    /// through a [ClusterEvent::ApplicationStateChanged] event.
+    ///
+    /// Application state is meant for small values: it is carried by every gossip exchange.
+    pub async fn set_local_state(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.main_thread_message_sender
+            .send(Message::SetLocalState {
+                key: key.into(),
+                value: value.into(),
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Gracefully leaves the cluster.
+    ///
+    /// The running node marks itself as [Leaving](node::NodeStatus::Leaving), then keeps gossiping until the leader
+    /// has moved it to [Exiting](node::NodeStatus::Exiting) and the rest of the cluster has observed its departure.
+    /// The main task of this instance is then stopped, and the returned future completes.
+    ///
+    /// Leaving requires the cluster to converge, which can take several gossip rounds. Wrap the returned future in
+    /// a timeout if you need to bound the wait.
+    pub async fn leave(&self) -> Result<(), Box<dyn Error>> {
+        let (tx, rx) = oneshot::channel();
+        self.main_thread_message_sender
+            .send(Message::Leave { reply: tx })
+            .await?;
+        Ok(rx.await?)
+    }
+
+    /// Marks a member as [Down](node::NodeStatus::Down), e.g. to force the removal of a node that is wedged
+    /// and will never leave gracefully. The leader then removes the member once the cluster has converged.
+    ///
+    /// The downed member is quarantined: it remains down even if its stale gossip reaches the cluster later on.
+    /// The node has to be restarted, with a new [NodeId], to join the cluster again.
+    /// Returns false if the member is unknown, or has already been downed or removed.
+    pub async fn down(&self, node_id: NodeId) -> Result<bool, Box<dyn Error>> {
         let (tx, rx) = oneshot::channel();
         self.main_thread_message_sender
-            .send(Message::GetCurrentClusterClone { reply: tx })
+            .send(Message::Down { node_id, reply: tx })
             .await?;
         Ok(rx.await?)
     }
@@ -83,30 +212,131 @@ impl Council {
         mut cluster: Cluster,
         mut message_receiver: mpsc::Receiver<Message>,
         mut message_sender: mpsc::Sender<Message>,
-        mut cluster_events_sender: broadcast::Sender<ClusterEvent>,
+        event_publisher: EventPublisher,
+        cluster_sender: watch::Sender<Arc<Cluster>>,
         client: Arc<CouncilClient>,
     ) {
+        let mut leave_replies: Vec<oneshot::Sender<()>> = Vec::new();
+        // The last reason each member has given for denying the running node admission into the cluster
+        let mut admission_denials: HashMap<Url, String> = HashMap::new();
+        let mut heartbeat_interval = tokio::time::interval(cluster.heartbeat_interval);
         outgoing_gossip_interval.tick().await;
+        heartbeat_interval.tick().await;
         loop {
-            select! {
+            let cluster_changed = select! {
                 Some(incoming_message) = message_receiver.recv() => {
                     match incoming_message {
                         Message::ReconcileClusterView { incoming_cluster_view, reconciled_cluster_view_reply } =>
                         {
-                            handle_incoming_cluster_view(&mut cluster, incoming_cluster_view, reconciled_cluster_view_reply, &mut cluster_events_sender).await;
+                            handle_incoming_cluster_view(&mut cluster, incoming_cluster_view, reconciled_cluster_view_reply).await;
+                            true
                         },
-                        Message::GetCurrentClusterClone { reply} => {
-                            let _ = reply.send(cluster.clone());
+                        Message::ReconcileDigest { incoming_digest, incoming_delta, reply } => {
+                            if let Some(incoming_delta) = incoming_delta {
+                                handle_incoming_cluster_view(&mut cluster, incoming_delta, None).await;
+                            }
+                            match cluster.admission_denial(incoming_digest.this_node_id) {
+                                Some(reason) => {
+                                    let _ = reply.send(Err(AdmissionDenied(reason.to_string())));
+                                }
+                                None => {
+                                    cluster.record_digest_heartbeats(&incoming_digest);
+                                    let _ = reply.send(Ok((cluster.digest(), cluster.delta_for(&incoming_digest))));
+                                }
+                            }
+                            true
+                        }
+                        Message::Probe { reply } => {
+                            let _ = reply.send((cluster.this_node_id, cluster.is_formed()));
+                            false
+                        }
+                        Message::ProbeAnswers { answers } => {
+                            cluster.handle_probe_answers(answers, Instant::now())
+                        }
+                        Message::Ping { target, reply } => {
+                            let ack = if target == cluster.this_node_id { cluster.ping_ack() } else { None };
+                            let _ = reply.send(ack);
+                            false
+                        }
+                        Message::PingReq { target, target_advertised_url, reply } => {
+                            let client = Arc::clone(&client);
+                            let probe_timeout = cluster.probe_timeout();
+                            tokio::spawn(async move {
+                                let ack = timeout(probe_timeout, client.ping(target_advertised_url, target)).await;
+                                let _ = reply.send(ack.ok().and_then(Result::ok));
+                            });
+                            false
+                        }
+                        Message::PingResult { target, ack } => {
+                            cluster.handle_ping_result(target, ack)
+                        }
+                        Message::Heartbeat { from, heartbeat } => {
+                            cluster.record_heartbeat(from, heartbeat, Instant::now());
+                            false
+                        }
+                        Message::AdmissionDenied { by, reason } => {
+                            if admission_denials.get(&by) != Some(&reason) {
+                                admission_denials.insert(by.clone(), reason.clone());
+                                event_publisher.publish(ClusterEvent::AdmissionDenied { by, reason });
+                            }
+                            false
+                        }
+                        Message::ForeignClusterContact { cluster_name, remote_addr } => {
+                            event_publisher.publish(ClusterEvent::ForeignClusterContact { cluster_name, remote_addr });
+                            false
+                        }
+                        Message::SetLocalState { key, value } => {
+                            cluster.set_local_state(key, value)
+                        }
+                        Message::Down { node_id, reply } => {
+                            let downed = cluster.down(node_id);
+                            let _ = reply.send(downed);
+                            downed
+                        }
+                        Message::Leave { reply } => {
+                            leave_replies.push(reply);
+                            cluster.mark_own_node_as_leaving()
                         }
                     }
                 },
+                _ = heartbeat_interval.tick() => {
+                    let heartbeat = cluster.increment_own_heartbeat();
+                    send_heartbeats(&cluster, heartbeat, &client);
+                    // The heartbeat of the running node alone isn't worth publishing a new snapshot for
+                    false
+                }
                 _ = outgoing_gossip_interval.tick() => {
-                    cluster.increment_own_heartbeat();
-
-                    // TODO: take leadership action here!
+                    let mut changed = cluster.update_reachability(Instant::now());
+                    changed |= cluster.down_restarted_members(Instant::now());
+                    changed |= cluster.perform_downing(Instant::now());
+                    changed |= cluster.perform_leader_actions();
                     gossip(&mut cluster, &client, &mut message_sender).await;
-                    // TODO: implement garbage collection here ?
+                    probe_contact_points(&cluster, &client, &message_sender, outgoing_gossip_interval.period());
+                    if let Some(target) = cluster.next_ping_target() {
+                        ping(target, cluster.probe_timeout(), &client, &message_sender);
+                    }
+                    changed |= cluster.collect_garbage(SystemTime::now());
+                    changed
                 }
+            };
+
+            if cluster_changed {
+                // Notify outside subscribers of what has changed in the cluster
+                event_publisher
+                    .publish_membership(cluster.this_node_id, MembershipSnapshot::of(&cluster));
+                cluster_sender.send_replace(Arc::new(cluster.clone()));
+            }
+
+            if !leave_replies.is_empty() && cluster.has_left() {
+                log::info!(
+                    "[Node id: {}] The cluster has acknowledged our departure",
+                    cluster.this_node_id
+                );
+                for reply in leave_replies.drain(..) {
+                    let _ = reply.send(());
+                }
+                event_publisher.close();
+                return;
             }
         }
     }
@@ -115,7 +345,8 @@ impl Council {
 impl Drop for Council {
     fn drop(&mut self) {
         log::info!("Council instance {} is shutting down!", self.this_node_id);
-        // TODO: graceful shutdown!
+        self.main_thread.abort();
+        self.event_publisher.close();
     }
 }
 
@@ -125,27 +356,233 @@ async fn gossip(
     message_sender: &mut mpsc::Sender<Message>,
 ) {
     for dest in cluster.select_gossip_destinations() {
-        let client = Arc::clone(&client);
+        let client = Arc::clone(client);
         let message_sender = message_sender.clone();
+        let this_node_id = cluster.this_node_id;
         tokio::spawn(async move {
-            if let Ok(res) = client
-                .exchange_cluster_views(dest.destination_url, dest.cluster_view)
-                .await
-            {
-                let _ = message_sender.send(Message::ReconcileClusterView {
-                    incoming_cluster_view: res,
-                    reconciled_cluster_view_reply: None,
-                });
+            let destination_url = dest.destination_url;
+            let result = match (dest.digest, dest.full_view) {
+                (Some(digest), full_view) => {
+                    match exchange_digests(&destination_url, digest, &client, &message_sender).await
+                    {
+                        Err(e) if is_unimplemented(e.as_ref()) => match full_view {
+                            // The destination predates digest gossip
+                            Some(full_view) => {
+                                exchange_cluster_views(
+                                    &destination_url,
+                                    full_view,
+                                    &client,
+                                    &message_sender,
+                                )
+                                .await
+                            }
+                            None => Err(e),
+                        },
+                        result => result,
+                    }
+                }
+                (None, Some(full_view)) => {
+                    exchange_cluster_views(&destination_url, full_view, &client, &message_sender)
+                        .await
+                }
+                (None, None) => Ok(()),
+            };
+            if let Err(e) = result {
+                if let Some(AdmissionDenied(reason)) = e.downcast_ref::<AdmissionDenied>() {
+                    log::warn!(
+                        "[Node id: {}] {} has denied us admission into the cluster: {}",
+                        this_node_id,
+                        destination_url,
+                        reason
+                    );
+                    let _ = message_sender
+                        .send(Message::AdmissionDenied {
+                            by: destination_url,
+                            reason: reason.clone(),
+                        })
+                        .await;
+                    return;
+                }
+                log::debug!(
+                    "[Node id: {}] Failed to gossip with {}: {}",
+                    this_node_id,
+                    destination_url,
+                    e
+                );
             }
         });
     }
 }
 
+/// Sends the heartbeat of the running node to the members monitoring it.
+/// Heartbeats that fail to be delivered are not retried: the failure detectors of their receivers are there to notice.
+fn send_heartbeats(cluster: &Cluster, heartbeat: u64, client: &Arc<CouncilClient>) {
+    let this_node_id = cluster.this_node_id;
+    let heartbeat_timeout = cluster.heartbeat_interval;
+    for url in cluster.heartbeat_receivers() {
+        let client = Arc::clone(client);
+        tokio::spawn(async move {
+            let _ = timeout(
+                heartbeat_timeout,
+                client.send_heartbeat(url, this_node_id, heartbeat),
+            )
+            .await;
+        });
+    }
+}
+
+/// Probes the contact points of a bootstrapping node, then hands the answers over to the main loop.
+/// Probes that take longer than `probe_timeout` are considered unanswered.
+fn probe_contact_points(
+    cluster: &Cluster,
+    client: &Arc<CouncilClient>,
+    message_sender: &mpsc::Sender<Message>,
+    probe_timeout: Duration,
+) {
+    let contact_points = cluster.contact_points_to_probe();
+    if contact_points.is_empty() {
+        return;
+    }
+    let this_node_id = cluster.this_node_id;
+    let client = Arc::clone(client);
+    let message_sender = message_sender.clone();
+    tokio::spawn(async move {
+        let probes: Vec<_> = contact_points
+            .into_iter()
+            .map(|url| {
+                let client = Arc::clone(&client);
+                tokio::spawn(async move {
+                    let answer =
+                        timeout(probe_timeout, client.probe(url.clone(), this_node_id)).await;
+                    match answer {
+                        Ok(Ok(cluster_formed)) => Some((url, cluster_formed)),
+                        _ => None,
+                    }
+                })
+            })
+            .collect();
+        let mut answers = HashMap::new();
+        for probe in probes {
+            if let Ok(Some((url, cluster_formed))) = probe.await {
+                answers.insert(url, cluster_formed);
+            }
+        }
+        let _ = message_sender.send(Message::ProbeAnswers { answers }).await;
+    });
+}
+
+/// Pings a member directly, then through other members if it doesn't answer in time,
+/// and hands the outcome over to the main loop
+fn ping(
+    target: PingTarget,
+    probe_timeout: Duration,
+    client: &Arc<CouncilClient>,
+    message_sender: &mpsc::Sender<Message>,
+) {
+    let client = Arc::clone(client);
+    let message_sender = message_sender.clone();
+    tokio::spawn(async move {
+        let direct_ack = timeout(
+            probe_timeout,
+            client.ping(target.advertised_url.clone(), target.node_id),
+        )
+        .await;
+        let ack = match direct_ack {
+            Ok(Ok(ack)) => Some(ack),
+            _ => {
+                let indirect_pings: Vec<_> = target
+                    .helpers
+                    .into_iter()
+                    .map(|helper| {
+                        let client = Arc::clone(&client);
+                        let target_url = target.advertised_url.clone();
+                        tokio::spawn(async move {
+                            timeout(
+                                probe_timeout * 2,
+                                client.ping_req(helper, target.node_id, target_url),
+                            )
+                            .await
+                        })
+                    })
+                    .collect();
+                let mut ack = None;
+                for indirect_ping in indirect_pings {
+                    if let Ok(Ok(Ok(indirect_ack))) = indirect_ping.await {
+                        ack = Some(indirect_ack);
+                        break;
+                    }
+                }
+                ack
+            }
+        };
+        let _ = message_sender
+            .send(Message::PingResult {
+                target: target.node_id,
+                ack,
+            })
+            .await;
+    });
+}
+
+/// Gossips with a destination in two steps:
+/// - we send our digest, the destination replies with its own digest and the member views we are behind on
+/// - we merge those views, then push the member views the destination is behind on, if any
+async fn exchange_digests(
+    destination_url: &Url,
+    digest: ClusterDigest,
+    client: &CouncilClient,
+    message_sender: &mpsc::Sender<Message>,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let (incoming_digest, incoming_delta) = client
+        .exchange_digests(destination_url.clone(), digest)
+        .await?;
+    let (reply_tx, reply_rx) = oneshot::channel();
+    message_sender
+        .send(Message::ReconcileDigest {
+            incoming_digest: Box::new(incoming_digest),
+            incoming_delta: Some(incoming_delta),
+            reply: reply_tx,
+        })
+        .await?;
+    let (_, outgoing_delta) = reply_rx.await??;
+    if !outgoing_delta.is_empty() {
+        client
+            .push_delta(destination_url.clone(), outgoing_delta)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Sends the full view of the running node to a peer that doesn't gossip digests, and merges the view it replies with
+async fn exchange_cluster_views(
+    destination_url: &Url,
+    full_view: PartialClusterView,
+    client: &CouncilClient,
+    message_sender: &mpsc::Sender<Message>,
+) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let incoming_cluster_view = client
+        .exchange_cluster_views(destination_url.clone(), full_view)
+        .await?;
+    message_sender
+        .send(Message::ReconcileClusterView {
+            incoming_cluster_view,
+            reconciled_cluster_view_reply: None,
+        })
+        .await?;
+    Ok(())
+}
+
+/// Tells whether a request failed because the peer doesn't implement the called method
+fn is_unimplemented(error: &(dyn Error + Send + Sync + 'static)) -> bool {
+    error
+        .downcast_ref::<tonic::Status>()
+        .is_some_and(|status| status.code() == tonic::Code::Unimplemented)
+}
+
 async fn handle_incoming_cluster_view(
     cluster: &mut Cluster,
-    incoming_cluster_view: PartialClusterView,
+    mut incoming_cluster_view: PartialClusterView,
     reply: Option<oneshot::Sender<PartialClusterView>>,
-    cluster_event_sender: &mut broadcast::Sender<ClusterEvent>,
 ) {
     let incoming_node_id = incoming_cluster_view.this_node_id;
 
@@ -162,16 +599,26 @@ async fn handle_incoming_cluster_view(
         incoming_cluster_view.members.len()
     );
 
+    // Views sent by nodes that are denied admission are ignored, and so are the denied nodes they carry
+    if !cluster.filter_admitted_members(&mut incoming_cluster_view, SystemTime::now()) {
+        return;
+    }
+
+    // Tombstones come first, so that no stale view of a removed member is merged
+    cluster.merge_tombstones(incoming_cluster_view.tombstones, SystemTime::now());
+
     for (_, mut member) in incoming_cluster_view.members {
         if member.id == incoming_node_id {
             cluster.unknwon_peer_nodes.remove(&member.advertised_addr);
         }
 
         if let Some(state) = &mut member.state {
-            if member.id != cluster.this_node_id {
-                cluster
-                    .failure_detector
-                    .record_heartbeat(member.id, state.heartbeat);
+            if member.id != cluster.this_node_id && !cluster.is_removed(member.id) {
+                cluster.failure_detector.record_heartbeat(
+                    member.id,
+                    state.heartbeat,
+                    Instant::now(),
+                );
             }
         }
 
@@ -179,21 +626,21 @@ async fn handle_incoming_cluster_view(
             .cluster_view
             .merge_member_view(cluster.this_node_id, member);
     }
+    // The quarantine comes after the member views, since only members known to be down can be quarantined
+    cluster
+        .cluster_view
+        .merge_quarantine(incoming_cluster_view.quarantine);
 
-    // Notify outside subscribers that the cluster state has changed
-    if cluster_event_sender.receiver_count() > 0 {
-        let _ = cluster_event_sender.send(ClusterEvent {
-            cluster: Arc::new(cluster.clone()),
-        });
-    }
+    cluster
+        .cluster_view
+        .merge_reachability(incoming_cluster_view.reachability);
+
+    // Other members may suspect the running node of having failed
+    cluster.refute_suspicion();
 
     // Reply to the initiator of the gossip request, if applicable
     if let Some(reply) = reply {
-        let partial_cluster_view_to_send = PartialClusterView {
-            this_node_id: cluster.this_node_id,
-            members: cluster.cluster_view.known_members.clone(),
-        };
-        let _ = reply.send(partial_cluster_view_to_send);
+        let _ = reply.send(cluster.full_view());
     }
 }
 
@@ -205,20 +652,176 @@ enum Message {
         incoming_cluster_view: PartialClusterView,
         reconciled_cluster_view_reply: Option<oneshot::Sender<PartialClusterView>>,
     },
-    GetCurrentClusterClone {
-        reply: oneshot::Sender<Cluster>,
+    /// Reconciles the digest of a peer, along with the member views it sent us if any.
+    /// The reply carries our own digest and the member views the peer is behind on.
+    ReconcileDigest {
+        incoming_digest: Box<ClusterDigest>,
+        incoming_delta: Option<PartialClusterView>,
+        reply: oneshot::Sender<Result<(ClusterDigest, PartialClusterView), AdmissionDenied>>,
+    },
+    /// Asks whether the running node is part of a formed cluster, on behalf of a bootstrapping node
+    Probe {
+        reply: oneshot::Sender<(NodeId, bool)>,
+    },
+    /// The answers of the contact points probed by the running node, while it is bootstrapping
+    ProbeAnswers {
+        answers: HashMap<Url, bool>,
+    },
+    /// Asks the running node to acknowledge a ping, provided it is the target of the ping
+    Ping {
+        target: NodeId,
+        reply: oneshot::Sender<Option<PingAck>>,
+    },
+    /// Asks the running node to ping a member on behalf of another member
+    PingReq {
+        target: NodeId,
+        target_advertised_url: Url,
+        reply: oneshot::Sender<Option<PingAck>>,
+    },
+    /// The outcome of a probe started by the running node
+    PingResult {
+        target: NodeId,
+        ack: Option<PingAck>,
+    },
+    /// The heartbeat of a member monitored by the running node
+    Heartbeat {
+        from: NodeId,
+        heartbeat: u64,
+    },
+    SetLocalState {
+        key: String,
+        value: String,
+    },
+    Down {
+        node_id: NodeId,
+        reply: oneshot::Sender<bool>,
+    },
+    Leave {
+        reply: oneshot::Sender<()>,
+    },
+    /// A member has denied the running node admission into the cluster
+    AdmissionDenied {
+        by: Url,
+        reason: String,
+    },
+    /// A node of another cluster has sent a message to the running node, which has been rejected
+    ForeignClusterContact {
+        cluster_name: String,
+        remote_addr: Option<SocketAddr>,
     },
 }
 
-#[derive(Debug, Clone)]
-pub struct ClusterEvent {
-    pub cluster: Arc<Cluster>,
-}
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
 
-impl Deref for ClusterEvent {
-    type Target = Cluster;
+    use tokio::time::timeout;
+    use tokio_stream::StreamExt;
 
-    fn deref(&self) -> &Self::Target {
-        self.cluster.deref()
+    use super::*;
+    use crate::node::NodeStatus;
+
+    fn single_node_council() -> Council {
+        Council::builder(Url::parse("http://localhost:8080").unwrap())
+            .with_gossip_interval(Duration::from_millis(10))
+            .with_heartbeat_interval(Duration::from_millis(10))
+            .build()
+    }
+
+    #[test]
+    #[should_panic(expected = "The heartbeat interval must be greater than zero")]
+    fn zero_heartbeat_intervals_are_rejected_by_the_builder() {
+        Council::builder(Url::parse("http://localhost:8080").unwrap())
+            .with_heartbeat_interval(Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn single_node_moves_itself_up_then_leaves() {
+        let council = single_node_council();
+        let mut events = council.subscribe(SubscriptionMode::InitialStateAsEvents);
+
+        let this_node_is_up = async {
+            while let Some(event) = events.next().await {
+                if let ClusterEvent::MemberUp(member) = event {
+                    assert_eq!(member.id, council.this_node_id);
+                    return;
+                }
+            }
+        };
+        timeout(Duration::from_secs(5), this_node_is_up)
+            .await
+            .expect("The node should have been moved up");
+
+        timeout(Duration::from_secs(5), council.leave())
+            .await
+            .expect("The node should have left the cluster")
+            .unwrap();
+
+        let exited = events.any(|event| {
+            matches!(event, ClusterEvent::MemberExited(member) if member.status == NodeStatus::Exiting)
+        });
+        assert!(timeout(Duration::from_secs(5), exited).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn cluster_snapshots_are_published_after_every_change() {
+        let council = single_node_council();
+
+        council.set_local_state("load", "0.5").await.unwrap();
+        let state_changed = async {
+            loop {
+                let changed = council.changed().await.unwrap();
+                if changed.cluster_view.known_members[&council.this_node_id]
+                    .application_state_value("load")
+                    == Some("0.5")
+                {
+                    return;
+                }
+            }
+        };
+        timeout(Duration::from_secs(5), state_changed)
+            .await
+            .expect("A snapshot with the new application state should have been published");
+    }
+
+    #[tokio::test]
+    async fn new_subscribers_receive_the_initial_state() {
+        let council = single_node_council();
+        let mut events = council.subscribe(SubscriptionMode::InitialStateAsEvents);
+
+        let first_event = timeout(Duration::from_secs(5), events.next())
+            .await
+            .unwrap()
+            .unwrap();
+        match first_event {
+            ClusterEvent::MemberJoined(member) | ClusterEvent::MemberUp(member) => {
+                assert_eq!(member.id, council.this_node_id)
+            }
+            event => panic!("Unexpected initial event {:?}", event),
+        }
+    }
+
+    #[tokio::test]
+    async fn local_state_changes_are_published_as_events() {
+        let council = single_node_council();
+        let mut events = council.subscribe(SubscriptionMode::InitialStateAsEvents);
+        council.set_local_state("ready", "true").await.unwrap();
+
+        let state_changed = events.any(|event| {
+            event
+                == ClusterEvent::ApplicationStateChanged {
+                    node_id: council.this_node_id,
+                    key: "ready".to_string(),
+                    value: "true".to_string(),
+                }
+        });
+        assert!(timeout(Duration::from_secs(5), state_changed)
+            .await
+            .unwrap());
+        assert_eq!(
+            council.current_cluster().cluster_view.known_members[&council.this_node_id]
+                .application_state_value("ready"),
+            Some("true")
+        );
     }
 }
@@ -20,8 +20,10 @@ pub struct NodeId {
     /// must be unique troughout the entire cluster
     pub unique_id: u64,
     /// This field is used to disambiguate successive restarts of the same node.
-    /// It is simply the number of seconds elapsed since the UNIX epoch at the time the node was started.
-    /// When a node restarts after exiting the cluster, it will have a different generation value.
+    /// It is simply the number of nanoseconds elapsed since the UNIX epoch at the time the node was started,
+    /// so that even a node restarting within the same second gets a different generation value.
+    /// Older builds counted seconds instead, so generations are compared through [NodeId::incarnation].
+    /// When the cluster learns about a newer generation of a member, it [downs](NodeStatus::Down) the previous one.
     /// When reconciling two views of the same node, the most recent generation always wins.
     /// If the two views have an identical generation field, then we use the version field to reconcile them.
     pub generation: u64,
@@ -30,10 +32,13 @@ pub struct NodeId {
 impl NodeId {
     /// Builds a [NodeId] whose value is a hash of the provided URL
     pub fn from_url(url: &Url, node_started_at: SystemTime) -> Self {
-        let generation = node_started_at
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        let generation = u64::try_from(
+            node_started_at
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos(),
+        )
+        .expect("Nanoseconds since the Unix epoch fit in a u64 until 2554");
         let mut hasher = siphasher::sip::SipHasher::new();
         url.hash(&mut hasher);
         Self {
@@ -41,8 +46,24 @@ impl NodeId {
             generation,
         }
     }
+
+    /// Orders the successive incarnations of a node, from the first one started to the latest.
+    /// Builds that predate nanosecond generations count seconds since the UNIX epoch, so their generations are
+    /// converted to nanoseconds: members started by both kinds of builds can be told apart during a rolling upgrade.
+    pub(crate) fn incarnation(&self) -> (u64, u64) {
+        let nanos = if self.generation < SECONDS_GENERATIONS_BOUND {
+            self.generation.saturating_mul(1_000_000_000)
+        } else {
+            self.generation
+        };
+        (nanos, self.generation)
+    }
 }
 
+/// Generations below this bound count seconds: it is reached in nanoseconds 16 minutes after the UNIX epoch, and
+/// in seconds in the year 33658
+const SECONDS_GENERATIONS_BOUND: u64 = 1_000_000_000_000;
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct ParseNodeIdError;
 
@@ -89,6 +110,7 @@ pub struct Version {
     number: u64,
 }
 
+#[allow(dead_code)]
 impl Version {
     pub(crate) fn new() -> Version {
         Version { number: 0 }
@@ -121,6 +143,10 @@ pub enum NodeStatus {
     // The Down status is final.
     //A down node can never be marked up again unless the node is entirely restarted.
     Down = 5,
+    // Upon convergence, an exiting or down node was removed from the cluster by the cluster leader
+    // The Removed status is terminal: removed nodes are pruned from every view, and are only remembered
+    // through tombstones for a while, so that stale gossip can't bring them back.
+    Removed = 6,
 }
 
 impl Display for NodeStatus {
@@ -131,6 +157,7 @@ impl Display for NodeStatus {
             NodeStatus::Leaving => f.write_str("Leaving"),
             NodeStatus::Exiting => f.write_str("Exiting"),
             NodeStatus::Down => f.write_str("Down"),
+            NodeStatus::Removed => f.write_str("Removed"),
         }
     }
 }
@@ -145,6 +172,7 @@ impl Arbitrary for NodeStatus {
             Self::Leaving,
             Self::Exiting,
             Self::Down,
+            Self::Removed,
         ])
         .unwrap()
     }
@@ -0,0 +1,67 @@
+//! Versioning of the gossip protocol, so that wire changes can be rolled out across a running cluster.
+//!
+//! Every gossip message carries the protocol version of its sender and the features it supports:
+//! - a node rejects messages from peers whose protocol version it doesn't support. A build supports the versions
+//!   from [MIN_SUPPORTED_PROTOCOL_VERSION] up to the version right after its own, so a cluster must be upgraded one
+//!   protocol version at a time
+//! - every member publishes its own [Protocol] along with its membership, so that the protocol of every member is
+//!   known across the cluster. The running node only uses a feature with the members that support it, and features
+//!   that involve the whole cluster are turned on once [every member supports them](crate::cluster::Cluster::all_members_support).
+//!
+//! Nodes built before protocol versioning speak version 0: they only exchange full cluster views, and don't send
+//! any header, so they can't tell which cluster they belong to: they are assumed to belong to the
+//! [default cluster](DEFAULT_CLUSTER_NAME), and nodes configured with another cluster name turn them away.
+use std::collections::BTreeSet;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The version of the gossip protocol spoken by this build
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The oldest version of the gossip protocol this build can talk to
+pub const MIN_SUPPORTED_PROTOCOL_VERSION: u32 = 0;
+
+/// The newest version of the gossip protocol this build can talk to, so that it keeps gossiping with the nodes
+/// that have already been upgraded to the next version during a rolling upgrade
+pub const MAX_SUPPORTED_PROTOCOL_VERSION: u32 = PROTOCOL_VERSION + 1;
+
+/// The name of the cluster a node belongs to unless [configured otherwise](crate::CouncilBuilder::with_cluster_name)
+pub const DEFAULT_CLUSTER_NAME: &str = "council";
+
+/// The optional features of the gossip protocol supported by this build:
+/// - `digest-gossip`: the member exchanges digests, then only the member views its peers are behind on. Other members
+///   are sent the full cluster view instead
+/// - `heartbeats`: the member accepts heartbeats from the members it monitors
+/// - `swim`: the member answers direct and indirect pings
+/// - `tombstones`: the member prunes removed members when it receives their tombstones. Removed members are only
+///   pruned once every member supports tombstones, otherwise the others would gossip them back
+pub const SUPPORTED_FEATURES: &[&str] = &["digest-gossip", "heartbeats", "swim", "tombstones"];
+
+/// The gossip protocol spoken by a member. Members that haven't published their protocol are assumed to speak
+/// version 0, which predates protocol versioning.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Protocol {
+    pub version: u32,
+    pub features: BTreeSet<String>,
+}
+
+impl Protocol {
+    /// The protocol spoken by this build
+    pub fn current() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            features: SUPPORTED_FEATURES.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    /// Tells whether this build can talk to a peer speaking the given protocol version
+    pub fn is_supported(version: u32) -> bool {
+        (MIN_SUPPORTED_PROTOCOL_VERSION..=MAX_SUPPORTED_PROTOCOL_VERSION).contains(&version)
+    }
+
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.contains(feature)
+    }
+}